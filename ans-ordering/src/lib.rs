@@ -6,10 +6,16 @@ use std::sync::mpsc;
 use std::fmt::{Display, Write};
 use std::fs::File;
 use std::io::Write as W2;
-use symbol_table::{ANSTableUniform, SymbolFrequencies};
+use symbol_table::{ANSTableUniform, SymbolFrequencies, UniformTable};
 use std::thread;
 use std::error::Error;
 
+mod optimizer;
+
+pub use optimizer::{
+    optimize_encode_table, score, Objective, Optimized, OptimizerConfig, Search, MAX_MESSAGES,
+};
+
 pub fn catalog_encoding_results(
     messages: &mut dyn Iterator<Item = Vec<u8>>,
     ansu: ANSTableUniform,
@@ -134,7 +140,7 @@ fn multi_threaded_encode_loop_2(messages: &[Vec<u8>], ansu: ANSTableUniform) ->
     let ansu_lock = Arc::new(RwLock::new(ansu));
 
     while !work.is_empty() {
-        let quantum = (work.len() + num_threads - 1) / num_threads;
+        let quantum = work.len().div_ceil(num_threads);
         let (lhs, rhs) = work.split_at(quantum);
         let span = lhs.to_vec();
         let ansu = ansu_lock.clone();
@@ -144,7 +150,7 @@ fn multi_threaded_encode_loop_2(messages: &[Vec<u8>], ansu: ANSTableUniform) ->
             let mut sum_bits = 0f64;
             let mut sum_prob = 0f64;
             for message in span {
-                let encoded = simple_encode(&ansu, &message);
+                let encoded = simple_encode(&*ansu, &message);
                 let probability = probability_of_message(&ansu, &message);
                 let num_encoded_bits = (1.max(encoded) as f64).log2();
                 sum_bits += probability * num_encoded_bits;
@@ -179,11 +185,9 @@ fn multi_threaded_encode_loop_2(messages: &[Vec<u8>], ansu: ANSTableUniform) ->
 }
 
 fn fname_for_unweighted(src: &str) -> String {
-    if src.ends_with(".txt") {
-        let x = format!("{}_u.txt", &src[..(src.len() - 4)]);
-        x
-    } else {
-        src.to_string() + "_u"
+    match src.strip_suffix(".txt") {
+        Some(stem) => format!("{}_u.txt", stem),
+        None => src.to_string() + "_u",
     }
 }
 
@@ -201,7 +205,8 @@ pub fn probability_of_message(ansu: &ANSTableUniform, message: &[u8]) -> f64 {
     rval
 }
 
-pub fn simple_encode(ansu: &ANSTableUniform, message: &[u8]) -> u64 {
+/// the unbounded coder: the whole message in one integer, with any table layout
+pub fn simple_encode<T: UniformTable<u8>>(ansu: &T, message: &[u8]) -> u64 {
     let mut x = 1;
     for &symbol in message {
         x = ansu.append_encode64(x, symbol);
@@ -215,7 +220,7 @@ where
     D: Display,
     I: Iterator<Item = T>,
 {
-    match iter.fold(None, |a: Option<String>, b| {
+    iter.fold(None, |a: Option<String>, b| {
         Some(match a {
             None => format!("{}", &b),
             Some(mut a) => {
@@ -223,10 +228,8 @@ where
                 a
             }
         })
-    }) {
-        None => String::new(),
-        Some(rval) => rval,
-    }
+    })
+    .unwrap_or_default()
 }
 
 pub fn debug_dump(p0: &ANSTableUniform) {
@@ -246,30 +249,20 @@ pub fn polarity_a() -> ANSTableUniform {
 
 /// specially constructed to test how the ordering of the uniform encoding tables affects compression efficiency
 pub fn polarity_b() -> ANSTableUniform {
-    let mut frequencies = [0; 256];
-    frequencies[0] = 3;
-    frequencies[1] = 1;
-    ANSTableUniform {
-        frequencies,
-        sum_frequencies: 4,
-        encode: vec![vec![1, 2, 3], vec![0]], // this is a special encoding that is different from our original calculations
-        decode: vec![],
-        verbose: false,
-    }
+    let mut symbol_frequencies = SymbolFrequencies::new();
+    symbol_frequencies.frequencies[0] = 3;
+    symbol_frequencies.frequencies[1] = 1;
+    // this is a special encoding that is different from our original calculations
+    ANSTableUniform::from_encode_table(symbol_frequencies, vec![vec![1, 2, 3], vec![0]]).unwrap()
 }
 
 /// specially constructed to test how the ordering of the uniform encoding tables affects compression efficiency
 pub fn polarity_c() -> ANSTableUniform {
-    let mut frequencies = [0; 256];
-    frequencies[0] = 3;
-    frequencies[1] = 1;
-    ANSTableUniform {
-        frequencies,
-        sum_frequencies: 4,
-        encode: vec![vec![0, 1, 2], vec![3]], // this is a special encoding that is different from our original calculations
-        decode: vec![],
-        verbose: false,
-    }
+    let mut symbol_frequencies = SymbolFrequencies::new();
+    symbol_frequencies.frequencies[0] = 3;
+    symbol_frequencies.frequencies[1] = 1;
+    // this is a special encoding that is different from our original calculations
+    ANSTableUniform::from_encode_table(symbol_frequencies, vec![vec![0, 1, 2], vec![3]]).unwrap()
 }
 
 pub fn binary_expand(packed_bits: i32, num_bits: u8) -> Vec<u8> {
@@ -295,6 +288,24 @@ pub fn quaternary_message_list(num_quats: u8) -> Box<dyn Iterator<Item = Vec<u8>
     Box::new((0..(1 << (2 * num_quats))).map(move |message| quaternary_expand(message, num_quats)))
 }
 
+/// every message of `length` symbols drawn from `symbols`.
+/// Panics if there are more of them than fit in a `usize`.
+pub fn alphabet_message_list(symbols: Vec<u8>, length: u8) -> Box<dyn Iterator<Item = Vec<u8>>> {
+    let count = symbols
+        .len()
+        .checked_pow(length as u32)
+        .unwrap_or_else(|| panic!("{}^{} messages do not fit in a usize", symbols.len(), length));
+    Box::new((0..count).map(move |mut packed| {
+        (0..length)
+            .map(|_| {
+                let symbol = symbols[packed % symbols.len()];
+                packed /= symbols.len();
+                symbol
+            })
+            .collect()
+    }))
+}
+
 pub fn quat_frequencies() -> SymbolFrequencies {
     let mut freqs = SymbolFrequencies::new();
     freqs.frequencies[0] = 1;
@@ -0,0 +1,260 @@
+//! Searching for the encode table that codes short messages best.
+//!
+//! The search starts from the best phase of the accumulator spread and then swaps the owners of
+//! two slots at a time, which always leaves a valid table.  Every candidate is scored over every message
+//! of `message_length` symbols, exactly like `catalog_encoding_results`, so keep the messages short.
+
+use crate::{alphabet_message_list, multi_threaded_encode_loop_2};
+use std::convert::TryFrom;
+use std::error::Error;
+use symbol_table::{ANSTableUniform, ExplicitEncodeTable, SpreadMethod, SymbolFrequencies};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Objective {
+    /// the probability-weighted average of log2 of the encoded value, which `catalog_encoding_results` reports
+    AverageBits,
+    /// log2 of the largest encoded value
+    WorstCaseBits,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Search {
+    /// only keep swaps that improve the score
+    LocalSearch,
+    /// also keep worse swaps with probability `exp(-delta/temperature)`, where the temperature
+    /// falls linearly from `initial_temperature` (in bits) to 0
+    Annealing { initial_temperature: f64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct OptimizerConfig {
+    pub message_length: u8,
+    pub objective: Objective,
+    pub search: Search,
+    /// how many slot swaps to try after the phase sweep
+    pub iterations: usize,
+    /// the phase sweep tries at most this many evenly spaced phases
+    pub max_phases: u32,
+    pub seed: u64,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        OptimizerConfig {
+            message_length: 8,
+            objective: Objective::AverageBits,
+            search: Search::LocalSearch,
+            iterations: 200,
+            max_phases: 64,
+            seed: 1,
+        }
+    }
+}
+
+/// every candidate is scored over all messages of `message_length` symbols, and there may be at most this many
+pub const MAX_MESSAGES: usize = 1 << 20;
+
+pub struct Optimized {
+    pub table: ANSTableUniform,
+    pub score: f64,
+    /// the accumulator phase the search started from
+    pub phase: u32,
+    pub evaluations: usize,
+}
+
+/// Find the encode table for `freqs` with the lowest `config.objective`.
+pub fn optimize_encode_table(
+    freqs: &SymbolFrequencies,
+    config: &OptimizerConfig,
+) -> Result<Optimized, Box<dyn Error>> {
+    let symbols: Vec<u8> = (0..=255u8)
+        .filter(|&symbol| freqs.frequencies[symbol as usize] > 0)
+        .collect();
+    if symbols.is_empty() {
+        return Err("can not optimize a table with no symbols".into());
+    }
+    let message_count = symbols
+        .len()
+        .checked_pow(config.message_length as u32)
+        .filter(|&count| count <= MAX_MESSAGES);
+    if message_count.is_none() {
+        return Err(format!(
+            "{} symbols make more than {} messages of {} symbols; shorten the messages",
+            symbols.len(),
+            MAX_MESSAGES,
+            config.message_length
+        )
+        .into());
+    }
+    let messages: Vec<Vec<u8>> = alphabet_message_list(symbols, config.message_length).collect();
+    let mut evaluations = 0;
+    let mut evaluate = |table: &ANSTableUniform| {
+        evaluations += 1;
+        score(&messages, table, config.objective)
+    };
+
+    let sum_frequencies = u32::try_from(freqs.total())
+        .map_err(|_| format!("frequencies sum to {}, more than a u32", freqs.total()))?;
+    let phase_step = sum_frequencies.div_ceil(config.max_phases.max(1)).max(1);
+    let mut best: Option<(ANSTableUniform, f64, u32)> = None;
+    for phase in (0..sum_frequencies).step_by(phase_step as usize) {
+        let method = SpreadMethod::Precise { phase };
+        let table = ANSTableUniform::with_strategy(freqs.clone(), &method)?;
+        let score = evaluate(&table);
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_score, _)| score < *best_score)
+        {
+            best = Some((table, score, phase));
+        }
+    }
+    let (best_table, best_score, phase) = best.ok_or("no phases to try")?;
+
+    let mut spread = spread_of(&best_table);
+    let mut current_score = best_score;
+    let mut best = (best_table, best_score);
+    let mut rng = XorShift(config.seed.max(1));
+    for iteration in 0..config.iterations {
+        let a = rng.below(spread.len());
+        let b = rng.below(spread.len());
+        if spread[a] == spread[b] {
+            continue;
+        }
+        spread.swap(a, b);
+        let table = table_from_spread(freqs, &spread)?;
+        let score = evaluate(&table);
+        let delta = score - current_score;
+        let accept = delta < 0.0
+            || match config.search {
+                Search::LocalSearch => false,
+                Search::Annealing {
+                    initial_temperature,
+                } => {
+                    let temperature =
+                        initial_temperature * (1.0 - iteration as f64 / config.iterations as f64);
+                    temperature > 0.0 && rng.unit() < (-delta / temperature).exp()
+                }
+            };
+        if accept {
+            current_score = score;
+            if score < best.1 {
+                best = (table, score);
+            }
+        } else {
+            spread.swap(a, b);
+        }
+    }
+
+    let (table, score) = best;
+    Ok(Optimized {
+        table,
+        score,
+        phase,
+        evaluations,
+    })
+}
+
+/// `config.objective` of `table` over `messages`
+pub fn score(messages: &[Vec<u8>], table: &ANSTableUniform, objective: Objective) -> f64 {
+    let (list, sum_bits, sum_prob) = multi_threaded_encode_loop_2(messages, table.clone());
+    match objective {
+        Objective::AverageBits => sum_bits / sum_prob,
+        Objective::WorstCaseBits => list
+            .iter()
+            .map(|&(_, encoded)| (1.max(encoded) as f64).log2())
+            .fold(0.0, f64::max),
+    }
+}
+
+/// the symbol that owns each slot
+fn spread_of(table: &ANSTableUniform) -> Vec<u8> {
+    table.decode.iter().map(|&(symbol, _)| symbol).collect()
+}
+
+fn table_from_spread(
+    freqs: &SymbolFrequencies,
+    spread: &[u8],
+) -> Result<ANSTableUniform, Box<dyn Error>> {
+    let mut encode = vec![Vec::new(); 256];
+    for (slot, &symbol) in spread.iter().enumerate() {
+        encode[symbol as usize].push(slot as u32);
+    }
+    Ok(ANSTableUniform::with_strategy(
+        freqs.clone(),
+        &ExplicitEncodeTable(encode),
+    )?)
+}
+
+/// deterministic, so a seed reproduces a search
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// uniform in `[0, 1)`
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        optimize_encode_table, quat_frequencies, quaternary_message_list, score, Objective,
+        OptimizerConfig, Search,
+    };
+    use symbol_table::{ANSTableUniform, SymbolFrequencies};
+
+    #[test]
+    fn never_worse_than_phase_0() {
+        let freqs = quat_frequencies();
+        let messages: Vec<Vec<u8>> = quaternary_message_list(5).collect();
+        let baseline = score(
+            &messages,
+            &ANSTableUniform::new(freqs.clone()),
+            Objective::AverageBits,
+        );
+
+        for &search in &[
+            Search::LocalSearch,
+            Search::Annealing {
+                initial_temperature: 0.05,
+            },
+        ] {
+            let config = OptimizerConfig {
+                message_length: 5,
+                search,
+                iterations: 50,
+                ..OptimizerConfig::default()
+            };
+            let optimized = optimize_encode_table(&freqs, &config).unwrap();
+            assert!(optimized.score <= baseline, "{:?}", search);
+            assert_eq!(
+                optimized.score,
+                score(&messages, &optimized.table, Objective::AverageBits)
+            );
+        }
+
+        // 256^8 messages would overflow a usize
+        let mut every_byte = SymbolFrequencies::new();
+        every_byte.frequencies.iter_mut().for_each(|f| *f = 1);
+        assert!(optimize_encode_table(&every_byte, &OptimizerConfig::default()).is_err());
+
+        // a sum past u32::MAX is not truncated
+        let mut huge = SymbolFrequencies::new();
+        huge.frequencies[..2].copy_from_slice(&[u32::MAX, 2]);
+        let err = optimize_encode_table(&huge, &OptimizerConfig::default())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("4294967297"), "{}", err);
+    }
+}
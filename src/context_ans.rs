@@ -0,0 +1,72 @@
+extern crate symbol_table;
+
+mod cliches;
+
+use crate::cliches::slurp;
+use std::env;
+use std::error::Error;
+use symbol_table::{
+    ContextFrequencies, NormalizationStrategy, StreamingANSContext, StreamingANSUniform,
+};
+
+/// Compare order-0 and order-1 (previous byte as context) compression of each file,
+/// using tables built from the file itself.
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args();
+    let args = args.skip(1);
+    let mut message_fnames: Vec<String> = args.collect();
+    if message_fnames.is_empty() {
+        message_fnames = [
+            "../test-data/at-the-mountains-of-madness.html",
+            "../test-data/dream-quest.html",
+        ]
+        .iter()
+        .map(|&str| str.to_string())
+        .collect()
+    }
+
+    for fname in message_fnames {
+        println!("#\t{}", fname);
+        let message = slurp(&fname)?;
+        analyze(&message)?;
+    }
+
+    Ok(())
+}
+
+fn analyze(message: &[u8]) -> Result<(), Box<dyn Error>> {
+    println!("orig\t{}", message.len());
+
+    let mut contexts = ContextFrequencies::new();
+    contexts.scan_file(&mut &message[..])?;
+    println!(
+        "#\t\tconditional entropy {:.4} bits/symbol",
+        contexts.conditional_entropy()
+    );
+
+    {
+        let precision_bits = 16;
+        let freqs = contexts
+            .order0()?
+            .normalize(precision_bits, NormalizationStrategy::Greedy)?
+            .frequencies;
+        let table_bits = freqs.compact_size_bits(Some(precision_bits))?;
+        let ansu = StreamingANSUniform::new(freqs, 16, 2);
+        let encoded = ansu.encode_slice(message, 1)?;
+        assert!(ansu.decode(&encoded, 1)? == message, "order-0 mismatch");
+        println!("order0\t{}\t+{} table", encoded.len(), table_bits / 8);
+    }
+
+    {
+        let precision_bits = 12;
+        let normalized = contexts.normalize(precision_bits, NormalizationStrategy::Greedy)?;
+        let mut tables = Vec::new();
+        normalized.write_context_tables(&mut tables, Some(precision_bits))?;
+        let coder = StreamingANSContext::new(&normalized, precision_bits, 16, 2)?;
+        let encoded = coder.encode(message, 1);
+        assert!(coder.decode(&encoded, 1)? == message, "order-1 mismatch");
+        println!("order1\t{}\t+{} tables", encoded.len(), tables.len());
+    }
+
+    Ok(())
+}
@@ -29,32 +29,17 @@ impl Eq for Datum {}
 
 impl PartialOrd<Datum> for Datum {
     fn partial_cmp(&self, other: &Datum) -> Option<Ordering> {
-        let a = self.probability.partial_cmp(&other.probability);
-        let b = self.encoded.partial_cmp(&other.encoded);
-
-        a.and_then(|a|
-            //b.and_then(|b| Some(a.reverse().then(b)))
-            b.map(|b| a.reverse().then(b)))
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Datum {
     fn cmp(&self, other: &Self) -> Ordering {
-        if true {
-            return self.partial_cmp(other).unwrap();
-        }
-
-        let a: Ordering = if self.probability < other.probability {
-            // this ignores NaNs and stuff
-            Ordering::Less
-        } else if self.probability > other.probability {
-            Ordering::Greater
-        } else {
-            Ordering::Equal
-        };
+        // this will panic on NaNs
+        let a = self.probability.partial_cmp(&other.probability).unwrap();
         let b = self.encoded.cmp(&other.encoded);
 
-        a.then(b)
+        a.reverse().then(b)
     }
 }
 
@@ -3,23 +3,52 @@ count the frequency of symbols (bytes) in a file.
 This frequency table will later be used by Asymmetrical Numerical System compression tools
 
 Usage:
-  $0 file1 [file2...] [ -o freqs.bin | -O freqs.txt ]
+  $0 [-w weight] file1 [[-w weight] file2...] [ -m weight table.bin ...] [ --scan-weight weight ]
+     [ -o freqs.bin | -O freqs.txt | --output freqs.{bin,ans,txt,lst,csv,json} ] [ -C contexts.bin ]
+     [ --conditional-entropy ] [ --report reference.bin ] [ -j threads ]
+  $0 --convert input.{bin,ans,txt,lst,csv,json} output.{bin,ans,txt,lst,csv,json}
 
   If no output file is specified, a verbose text readout will be sent to stdout
+
+  Symbols are counted in 64 bits.  If a count does not fit the 32-bit table, every count is divided by the
+  same power of two, and measure says so.
+
+  -j memory-maps each file and counts it on that many threads (0 for one per CPU).  The counts are the
+     same as the serial scan.  Context counting (-C, --conditional-entropy) is always serial.
+
+  -o writes the versioned binary table format (see symbol_table::TableHeader)
+
+  --output picks the format from the file extension (see symbol_table::TableFormat).
+     .txt is the same as -O, .lst is the verbose readout, .ans is the compact bit-packed table.
+
+  --convert rewrites a table in another format.  The precision recorded by the binary formats is preserved.
+
+  -C also counts each byte in the context of the previous one (order-1) and writes those 256 tables
+     with ContextFrequencies::write_context_tables
+
+  --conditional-entropy prints the order-1 conditional entropy of the input
+
+  --report prints the entropy of each input, its cross-entropy and KL divergence against the reference
+     table (in any format --output can read), the size they predict, and the ideal code length of each
+     symbol under both.
+
+  -w multiplies the counts of the file that follows by weight (order-0 only)
+
+  -m mixes the probabilities of an existing table into the result.  All the scanned files together are
+     one more component of the mix, weighted by --scan-weight (default 1).  The mixed table keeps the
+     total count of its components.  The table can be in any format --output can write.
  */
 
-extern crate byteorder;
 extern crate symbol_table;
 
-use byteorder::BigEndian;
-use byteorder::WriteBytesExt;
 use std::env;
 use std::fs::File;
-use std::io::{Error, Write};
-use symbol_table::SymbolFrequencies;
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+use symbol_table::{ContextFrequencies, SymbolCounts, SymbolFrequencies, TableFormat};
 
 trait SymbolTableSink {
-    fn output(&mut self, table: &[u32]) -> Result<(), Error>;
+    fn output(&mut self, table: &SymbolFrequencies) -> Result<(), Error>;
 }
 
 //
@@ -27,14 +56,8 @@ trait SymbolTableSink {
 struct StdoutSymbolTableSink {}
 
 impl SymbolTableSink for StdoutSymbolTableSink {
-    fn output(&mut self, table: &[u32]) -> Result<(), Error> {
-        for (symbol, &freq) in table.iter().enumerate() {
-            if freq > 0 {
-                println!("[{}]\tx {}", symbol, freq);
-            }
-        }
-
-        Ok(())
+    fn output(&mut self, table: &SymbolFrequencies) -> Result<(), Error> {
+        table.write_listing(&mut std::io::stdout())
     }
 }
 
@@ -51,10 +74,8 @@ impl<T: Write> BinarySymbolTableSink<T> {
 }
 
 impl<T: Write> SymbolTableSink for BinarySymbolTableSink<T> {
-    fn output(&mut self, table: &[u32]) -> Result<(), Error> {
-        for &freq in table {
-            self.sink.write_u32::<BigEndian>(freq)?;
-        }
+    fn output(&mut self, table: &SymbolFrequencies) -> Result<(), Error> {
+        table.write_symbol_table(&mut self.sink, None)?;
         println!("wrote binary results");
         Ok(())
     }
@@ -73,11 +94,28 @@ impl<T: Write> TextSymbolTableSink<T> {
 }
 
 impl<T: Write> SymbolTableSink for TextSymbolTableSink<T> {
-    fn output(&mut self, table: &[u32]) -> Result<(), Error> {
-        for (sym, freq) in table.iter().enumerate() {
-            writeln!(self.sink, "{} {}", sym, &freq)?;
-        }
+    fn output(&mut self, table: &SymbolFrequencies) -> Result<(), Error> {
+        table.write_text(&mut self.sink)
+    }
+}
 
+//
+
+struct FormatSymbolTableSink<T: Write> {
+    sink: T,
+    format: TableFormat,
+}
+
+impl<T: Write> FormatSymbolTableSink<T> {
+    fn new(sink: T, format: TableFormat) -> FormatSymbolTableSink<T> {
+        FormatSymbolTableSink { sink, format }
+    }
+}
+
+impl<T: Write> SymbolTableSink for FormatSymbolTableSink<T> {
+    fn output(&mut self, table: &SymbolFrequencies) -> Result<(), Error> {
+        table.write_table_format(&mut self.sink, self.format, None)?;
+        println!("wrote {:?} results", self.format);
         Ok(())
     }
 }
@@ -85,39 +123,241 @@ impl<T: Write> SymbolTableSink for TextSymbolTableSink<T> {
 //
 
 struct Mission {
-    fnames: Vec<String>,
+    /// file name and the weight of its counts
+    fnames: Vec<(String, f64)>,
+    /// table file name and its mixing weight
+    mix_tables: Vec<(String, f64)>,
+    scan_weight: f64,
     output: Box<dyn SymbolTableSink>,
+    context_output: Option<File>,
+    conditional_entropy: bool,
+    /// -j; `None` for the serial scan
+    threads: Option<usize>,
+    /// reference table file name for --report
+    report: Option<String>,
+    /// input and output file names for --convert
+    convert: Option<(String, String)>,
+}
+
+impl Mission {
+    fn needs_contexts(&self) -> bool {
+        self.context_output.is_some() || self.conditional_entropy
+    }
 }
 
 //
 
 //
 
-fn main() -> Result<(), Error> {
-    let mut table = SymbolFrequencies::new();
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut counts = SymbolCounts::new();
+    let mut contexts = ContextFrequencies::new();
 
     let args = env::args();
     let mut args = args.skip(1);
 
     let mut mission = args_to_mission(&mut args)?;
 
-    for fname in &mission.fnames {
-        let f = File::open(&fname);
+    if let Some((ifname, ofname)) = &mission.convert {
+        return convert(ifname, ofname);
+    }
+
+    let reference = match &mission.report {
+        Some(fname) => Some(read_table(fname)?),
+        None => None,
+    };
+
+    for (fname, weight) in &mission.fnames {
         println!("scanning symbols from {}", &fname);
 
-        if let Err(e) = f.and_then(|mut f| table.scan_file(&mut f)) {
-            println!("malfunction reading {} because {:?}", &fname, e);
+        let contexts = if mission.needs_contexts() {
+            Some(&mut contexts)
+        } else {
+            None
         };
+        match scan_file(fname, contexts, mission.threads) {
+            Ok(file_counts) => {
+                if let Some(reference) = &reference {
+                    report(fname, &downscale(&file_counts, fname), reference);
+                }
+                let file_counts = if *weight != 1.0 {
+                    file_counts.scale(*weight)?
+                } else {
+                    file_counts
+                };
+                counts = counts.checked_add(&file_counts)?;
+            }
+            Err(e) => println!("malfunction reading {} because {:?}", &fname, e),
+        }
     }
 
-    mission.output.output(&table.frequencies)?;
+    let mut table = downscale(&counts, "the combined counts");
+
+    if !mission.mix_tables.is_empty() {
+        table = mix_tables(table, mission.scan_weight, &mission.mix_tables)?;
+    }
+
+    mission.output.output(&table)?;
+
+    if mission.conditional_entropy {
+        println!(
+            "order-1 conditional entropy {:.4} bits/symbol",
+            contexts.conditional_entropy()
+        );
+    }
+    if let Some(mut f) = mission.context_output.take() {
+        contexts.write_context_tables(&mut f, None)?;
+        println!("wrote context tables");
+    }
 
     Ok(())
 }
 
+/// Count the symbols of one file.  If `contexts` is present, the order-1 counts are added to it as well;
+/// those are only 32 bits, so a file big enough to overflow them is an error.
+fn scan_file(
+    fname: &str,
+    contexts: Option<&mut ContextFrequencies>,
+    threads: Option<usize>,
+) -> Result<SymbolCounts, Box<dyn std::error::Error>> {
+    if let (None, Some(threads)) = (&contexts, threads) {
+        return Ok(SymbolCounts::scan_path_parallel(Path::new(fname), threads)?);
+    }
+
+    let mut f = File::open(fname)?;
+    match contexts {
+        None => {
+            let mut counts = SymbolCounts::new();
+            counts.scan_file(&mut f)?;
+            Ok(counts)
+        }
+        Some(contexts) => {
+            let mut file_contexts = ContextFrequencies::new();
+            file_contexts.scan_file(&mut f)?;
+            *contexts = contexts.checked_add(&file_contexts)?;
+            let mut counts = SymbolCounts::new();
+            for context in &file_contexts.contexts {
+                counts = counts.checked_add(&SymbolCounts::from(context))?;
+            }
+            Ok(counts)
+        }
+    }
+}
+
+/// fit the counts into a table, and tell the user if that cost any precision
+fn downscale(counts: &SymbolCounts, what: &str) -> SymbolFrequencies {
+    let downscaled = counts.downscale();
+    if downscaled.shift > 0 {
+        println!(
+            "{} overflow 32 bits; every count was divided by 2^{}",
+            what, downscaled.shift
+        );
+    }
+    downscaled.frequencies
+}
+
+fn mix_tables(
+    scanned: SymbolFrequencies,
+    scan_weight: f64,
+    mix_tables: &[(String, f64)],
+) -> Result<SymbolFrequencies, Box<dyn std::error::Error>> {
+    let mut tables = vec![(scanned, scan_weight)];
+    for (fname, weight) in mix_tables {
+        println!("mixing {} at weight {}", fname, weight);
+        tables.push((read_table(fname)?, *weight));
+    }
+
+    let total = tables.iter().map(|(table, _)| table.total()).sum();
+    let components: Vec<_> = tables
+        .iter()
+        .map(|(table, weight)| (table, *weight))
+        .collect();
+    Ok(SymbolFrequencies::mix(&components, total)?)
+}
+
+/// anything without a recognized extension is assumed to be binary, as it always was
+fn read_table(fname: &str) -> Result<SymbolFrequencies, Error> {
+    let mut f = File::open(fname)?;
+    let format = TableFormat::from_file_name(fname).unwrap_or(TableFormat::Binary);
+    let (table, _) = SymbolFrequencies::read_table_format(&mut f, format)?;
+    Ok(table)
+}
+
+fn report(fname: &str, histogram: &SymbolFrequencies, reference: &SymbolFrequencies) {
+    let entropy = histogram.entropy();
+    let cross_entropy = histogram.cross_entropy(reference);
+    println!("report for {}", fname);
+    println!("  symbols\t{}", histogram.total());
+    println!(
+        "  entropy\t{:.4} bits/symbol\t{:.0} bytes",
+        entropy,
+        histogram.predicted_size_bits(histogram) / 8.0
+    );
+    println!(
+        "  cross-entropy\t{:.4} bits/symbol\t{:.0} bytes",
+        cross_entropy,
+        histogram.predicted_size_bits(reference) / 8.0
+    );
+    println!(
+        "  KL divergence\t{:.4} bits/symbol",
+        histogram.kl_divergence(reference)
+    );
+
+    let own_lengths = histogram.code_lengths();
+    let reference_lengths = reference.code_lengths();
+    println!("  [sym]\tcount\tideal bits\treference bits");
+    for (symbol, &count) in histogram.frequencies.iter().enumerate() {
+        if count > 0 {
+            println!(
+                "  [{}]\t{}\t{:.3}\t{:.3}",
+                symbol, count, own_lengths[symbol], reference_lengths[symbol]
+            );
+        }
+    }
+}
+
+fn convert(ifname: &str, ofname: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (table, precision_bits) =
+        SymbolFrequencies::read_table_format(&mut File::open(ifname)?, format_of(ifname)?)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", ifname, e)))?;
+    let format = format_of(ofname)?;
+    table.write_table_format(&mut File::create(ofname)?, format, precision_bits)?;
+    println!("converted {} to {} ({:?})", ifname, ofname, format);
+    Ok(())
+}
+
+fn format_of(fname: &str) -> Result<TableFormat, Error> {
+    TableFormat::from_file_name(fname).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "can not tell the table format of {} from its extension",
+                fname
+            ),
+        )
+    })
+}
+
+fn parse_weight(arg: Option<String>) -> Result<f64, Error> {
+    let arg = arg.unwrap_or_default();
+    arg.parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("bad weight {:?}: {}", arg, e),
+        )
+    })
+}
+
 fn args_to_mission(args: &mut dyn Iterator<Item = String>) -> Result<Mission, Error> {
     let mut output: Box<dyn SymbolTableSink> = Box::new(StdoutSymbolTableSink {});
-    let mut fnames: Vec<String> = Vec::new();
+    let mut fnames: Vec<(String, f64)> = Vec::new();
+    let mut mix_tables = Vec::new();
+    let mut scan_weight = 1.0;
+    let mut context_output = None;
+    let mut conditional_entropy = false;
+    let mut threads = None;
+    let mut report = None;
+    let mut convert = None;
     //let mut output_file = None;
 
     loop {
@@ -136,10 +376,50 @@ fn args_to_mission(args: &mut dyn Iterator<Item = String>) -> Result<Mission, Er
             let ofname = args.next().unwrap();
             //output_file = Some(File::create(ofname)?);
             output = Box::new(TextSymbolTableSink::new(File::create(ofname)?));
+        } else if "--output" == arg {
+            let ofname = args.next().unwrap();
+            let format = format_of(&ofname)?;
+            output = Box::new(FormatSymbolTableSink::new(File::create(ofname)?, format));
+        } else if "-j" == arg {
+            let arg = args.next().unwrap_or_default();
+            threads = Some(arg.parse().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("bad thread count {:?}: {}", arg, e),
+                )
+            })?);
+        } else if "--report" == arg {
+            report = Some(args.next().unwrap());
+        } else if "--convert" == arg {
+            let ifname = args.next().unwrap();
+            convert = Some((ifname, args.next().unwrap()));
+        } else if "-C" == arg {
+            let ofname = args.next().unwrap();
+            context_output = Some(File::create(ofname)?);
+        } else if "--conditional-entropy" == arg {
+            conditional_entropy = true;
+        } else if "-w" == arg {
+            let weight = parse_weight(args.next())?;
+            fnames.push((args.next().unwrap(), weight));
+        } else if "-m" == arg {
+            let weight = parse_weight(args.next())?;
+            mix_tables.push((args.next().unwrap(), weight));
+        } else if "--scan-weight" == arg {
+            scan_weight = parse_weight(args.next())?;
         } else {
-            fnames.push(arg);
+            fnames.push((arg, 1.0));
         }
     }
 
-    Ok(Mission { fnames, output })
+    Ok(Mission {
+        fnames,
+        mix_tables,
+        scan_weight,
+        output,
+        context_output,
+        conditional_entropy,
+        threads,
+        report,
+        convert,
+    })
 }
@@ -0,0 +1,43 @@
+use ans_ordering::{
+    catalog_encoding_results, debug_dump, optimize_encode_table, quat_frequencies,
+    quaternary_message_list, Objective, OptimizerConfig, Search,
+};
+use std::env;
+use std::error::Error;
+
+/// Search for a better encode table for the quaternary distribution of `compare-ansu-phase`,
+/// then catalog 10-digit messages with it like `ordering-exp-10digit-4symbol` does.
+///
+/// usage: optimize-encode-table [message_length [iterations [initial_temperature]]]
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut config = OptimizerConfig::default();
+    if let Some(message_length) = args.first() {
+        config.message_length = message_length.parse()?;
+    }
+    if let Some(iterations) = args.get(1) {
+        config.iterations = iterations.parse()?;
+    }
+    if let Some(initial_temperature) = args.get(2) {
+        config.search = Search::Annealing {
+            initial_temperature: initial_temperature.parse()?,
+        };
+    }
+    config.objective = Objective::AverageBits;
+
+    let optimized = optimize_encode_table(&quat_frequencies(), &config)?;
+    println!(
+        "best phase {}; {:.6} bits after {} evaluations of {}-symbol messages",
+        optimized.phase, optimized.score, optimized.evaluations, config.message_length
+    );
+    debug_dump(&optimized.table);
+
+    let (_avg_bits, report) = catalog_encoding_results(
+        &mut quaternary_message_list(10),
+        optimized.table,
+        "/tmp/q-optimized.txt",
+    )?;
+    print!("{}", report);
+
+    Ok(())
+}
@@ -3,12 +3,11 @@ extern crate symbol_table;
 mod cliches;
 
 use crate::cliches::slurp;
-use cliches::scale_frequencies;
+use std::error::Error;
 use std::fs::File;
-use std::io::Error;
-use symbol_table::{StreamingANSUniform, SymbolFrequencies};
+use symbol_table::{NormalizationStrategy, StreamingANSUniform, SymbolFrequencies};
 
-fn main() -> Result<(), Error> {
+fn main() -> Result<(), Box<dyn Error>> {
     {
         println!("#\tat the mountains of madness");
         let symbol_fname = "../test-data/out/atmm.bin";
@@ -27,38 +26,54 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
-fn analyze(symbol_fname: &str, message2: &[u8]) -> Result<(), Error> {
+fn analyze(symbol_fname: &str, message2: &[u8]) -> Result<(), Box<dyn Error>> {
     println!("orig\t{}", message2.len());
 
+    let mut histogram = SymbolFrequencies::new();
+    histogram.add_symbols(message2.iter().cloned());
+    println!(
+        "#\t\tentropy {:.4} bits/symbol, at best {:.0}",
+        histogram.entropy(),
+        histogram.predicted_size_bits(&histogram) / 8.0
+    );
+
     {
-        let frequencies = build_flat_frequencies(&message2);
+        let frequencies = build_flat_frequencies(message2);
 
         let symbol_count = frequencies.iter().filter(|&&freq| freq != 0).count();
         println!("#\t\t{} distinct symbols in message", symbol_count);
 
-        {
-            let predicted = (message2.len() as f32) * (symbol_count as f32).ln() / 256f32.ln();
-            println!(
-                "#\t\tswitching to a flat symbol table of only used symbols will bring it near {}",
-                predicted
-            );
-        }
-
         let freqs = SymbolFrequencies { frequencies };
+        println!(
+            "#\t\tswitching to a flat symbol table of only used symbols will bring it near {:.0}",
+            histogram.predicted_size_bits(&freqs) / 8.0
+        );
+
+        // the stream needs a power-of-two table
+        let freqs = freqs
+            .normalize(16, NormalizationStrategy::Greedy)?
+            .frequencies;
         let ansu = StreamingANSUniform::new(freqs, 16, 2);
-        let encoded = ansu.encode(message2.iter().rev(), 1);
+        let encoded = ansu.encode_slice(message2, 1)?;
 
         println!("flat\t{}", encoded.len());
     }
 
     {
         let mut symbol_file = File::open(symbol_fname)?;
-        let freqs = SymbolFrequencies::parse_binary_symbol_table(&mut symbol_file)?;
+        let freqs = SymbolFrequencies::parse_symbol_table(&mut symbol_file)?;
 
-        let freqs = scale_frequencies(16, &freqs, false);
+        let freqs = freqs
+            .normalize(16, NormalizationStrategy::Greedy)?
+            .frequencies;
+        println!(
+            "#\t\tthe symbol table predicts {:.0} ({:.4} bits/symbol wasted)",
+            histogram.predicted_size_bits(&freqs) / 8.0,
+            histogram.kl_divergence(&freqs)
+        );
 
         let ansu = StreamingANSUniform::new(freqs, 16, 2);
-        let encoded_well = ansu.encode(message2.iter().rev(), 1);
+        let encoded_well = ansu.encode_slice(message2, 1)?;
 
         println!(
             "#\t\tencoding with a symbol table with asymmetric frequencies\nmatching\t{}",
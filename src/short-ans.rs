@@ -35,7 +35,7 @@ fn main() -> Result<(), Error> {
 
 fn demonstration1(fname: &str, test_data: &[u8]) -> Result<(), Error> {
     let mut symbol_file = File::open(fname)?;
-    let frequencies = SymbolFrequencies::parse_binary_symbol_table(&mut symbol_file)?;
+    let frequencies = SymbolFrequencies::parse_symbol_table(&mut symbol_file)?;
 
     let ans_table = ANSTableUniform::new(frequencies);
     //ans_table.verbose = true;
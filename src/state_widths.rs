@@ -0,0 +1,119 @@
+extern crate symbol_table;
+
+mod cliches;
+
+use crate::cliches::slurp;
+use std::env;
+use std::error::Error;
+use std::time::Instant;
+use symbol_table::{
+    check_stream_balance, ANSTableUniform, GenericStreamingANSUniform, NormalizationStrategy,
+    StateWord, SymbolFrequencies,
+};
+
+/// Compressed size and speed of the streaming coder for every state width, quantum and
+/// `underflow_bits` that balances with a table built from each file.
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args();
+    let args = args.skip(1);
+    let mut message_fnames: Vec<String> = args.collect();
+    if message_fnames.is_empty() {
+        message_fnames = [
+            "../test-data/at-the-mountains-of-madness.html",
+            "../test-data/dream-quest.html",
+            "../test-data/iso13818-2.pdf",
+        ]
+        .iter()
+        .map(|&str| str.to_string())
+        .collect()
+    }
+
+    println!("#\tbits\tstate\tquantum\tunderflow\tbytes\tencode MB/s\tdecode MB/s");
+    for fname in message_fnames {
+        println!("#\t{}", fname);
+        let message = slurp(&fname)?;
+        analyze(&message)?;
+    }
+
+    Ok(())
+}
+
+fn analyze(message: &[u8]) -> Result<(), Box<dyn Error>> {
+    println!("orig\t\t\t\t\t{}", message.len());
+
+    let mut raw = SymbolFrequencies::new();
+    raw.scan_file(&mut &message[..])?;
+
+    for &precision_bits in &[8, 12] {
+        let freqs = raw
+            .normalize(precision_bits, NormalizationStrategy::Greedy)?
+            .frequencies;
+        let table = ANSTableUniform::new(freqs);
+
+        for &quantum_bits in &[1, 4, 8, 16, 32] {
+            for underflow_bits in (8..=96).step_by(8) {
+                measure::<u32>(
+                    &table,
+                    precision_bits,
+                    underflow_bits,
+                    quantum_bits,
+                    message,
+                )?;
+                measure::<u64>(
+                    &table,
+                    precision_bits,
+                    underflow_bits,
+                    quantum_bits,
+                    message,
+                )?;
+                measure::<u128>(
+                    &table,
+                    precision_bits,
+                    underflow_bits,
+                    quantum_bits,
+                    message,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn measure<W: StateWord>(
+    table: &ANSTableUniform,
+    precision_bits: u8,
+    underflow_bits: u8,
+    quantum_bits: u8,
+    message: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    if check_stream_balance::<W, u8, _>(table, underflow_bits, quantum_bits).is_err() {
+        return Ok(());
+    }
+    let coder = GenericStreamingANSUniform::<u8, 256, ANSTableUniform, W>::with_quantum_bits(
+        table.clone(),
+        underflow_bits,
+        quantum_bits,
+    )?;
+
+    let start = Instant::now();
+    let encoded = coder.encode_slice(message, 1)?;
+    let encode_seconds = start.elapsed().as_secs_f64();
+    let start = Instant::now();
+    let decoded = coder.decode(&encoded, 1)?;
+    let decode_seconds = start.elapsed().as_secs_f64();
+    assert!(decoded == message, "mismatch");
+
+    let megabytes = message.len() as f64 / 1e6;
+    println!(
+        "\t{}\tu{}\t{}\t{}\t{}\t{:.1}\t{:.1}",
+        precision_bits,
+        W::BITS,
+        quantum_bits,
+        underflow_bits,
+        encoded.len(),
+        megabytes / encode_seconds,
+        megabytes / decode_seconds
+    );
+    Ok(())
+}
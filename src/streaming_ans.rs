@@ -7,9 +7,12 @@ use std::fs::File;
 use std::io::Write as Write1;
 use std::{io, panic};
 
-use symbol_table::{StreamingANSUniform, SymbolFrequencies};
+use symbol_table::{
+    ANSTableUniform, Diagnostics, NormalizationStrategy, StreamingANSEscaped, StreamingANSUniform,
+    SymbolFrequencies,
+};
 
-use crate::cliches::{scale_frequencies, slurp};
+use crate::cliches::slurp;
 use std::fmt::Write;
 use std::thread::spawn;
 
@@ -95,7 +98,7 @@ fn demo_suite1(
         //let payload:&[u8] = &payload;
         demonstration1(
             symbol_fname,
-            &payload,
+            payload,
             16,
             2,
             backfill_missing_symbols,
@@ -103,7 +106,7 @@ fn demo_suite1(
         )?;
         demonstration1(
             symbol_fname,
-            &payload,
+            payload,
             24,
             2,
             backfill_missing_symbols,
@@ -111,7 +114,7 @@ fn demo_suite1(
         )?;
         demonstration1(
             symbol_fname,
-            &payload,
+            payload,
             32,
             2,
             backfill_missing_symbols,
@@ -132,7 +135,21 @@ fn demonstration1(
 ) -> Result<(), Box<dyn Error>> {
     writeln!(sink, "demonstration(,,{})", underflow_bits)?;
     let mut symbol_file = File::open(symbol_fname)?;
-    let symbols = SymbolFrequencies::parse_binary_symbol_table(&mut symbol_file)?;
+    let symbols = SymbolFrequencies::parse_symbol_table(&mut symbol_file)?;
+
+    // unlike the plain coder below, this one survives bytes the table has never seen
+    let escaped = StreamingANSEscaped::new(&symbols, 16, underflow_bits, bytes_to_stream)?;
+    let compressed = escaped.encode(message, 1)?;
+    writeln!(
+        sink,
+        "escaped: compressed to {} bytes (UB={})",
+        compressed.len(),
+        underflow_bits
+    )?;
+    assert!(
+        escaped.decode(&compressed, 1)? == message,
+        "escaped encode/decode mismatch"
+    );
 
     let symbols = if backfill_missing_symbols {
         SymbolFrequencies::missing_symbols_become_one(&symbols)
@@ -140,9 +157,14 @@ fn demonstration1(
         symbols
     };
 
-    let symbols = scale_frequencies(16, &symbols, false);
+    let symbols = symbols
+        .normalize(16, NormalizationStrategy::Greedy)?
+        .frequencies;
 
-    let mut uans = StreamingANSUniform::new(symbols, underflow_bits, bytes_to_stream);
+    // show the balance warnings
+    let mut table = ANSTableUniform::new(symbols);
+    table.diagnostics = Diagnostics::stdout();
+    let mut uans = StreamingANSUniform::from_table(table, underflow_bits, bytes_to_stream);
     uans.verbose = false;
 
     demonstration1a(&mut uans, message, underflow_bits, sink)?;
@@ -156,7 +178,7 @@ fn demonstration1a(
     sink: &mut dyn Write,
 ) -> Result<(), Box<dyn Error>> {
     let iv = 1;
-    let compressed = uans.encode(message.iter().rev(), iv);
+    let compressed = uans.encode_slice(message, iv)?;
 
     writeln!(
         sink,
@@ -0,0 +1,74 @@
+extern crate symbol_table;
+
+mod cliches;
+
+use crate::cliches::slurp;
+use std::env;
+use std::error::Error;
+use symbol_table::{
+    NormalizationStrategy, RANSTable, SpreadMethod, StreamingANSUniform, StreamingRANS,
+    SymbolFrequencies, TabledANS,
+};
+
+/// Compare the tabled (tANS) and range (rANS) coders with `StreamingANSUniform` on each file,
+/// using tables built from the file itself at several precisions.
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args();
+    let args = args.skip(1);
+    let mut message_fnames: Vec<String> = args.collect();
+    if message_fnames.is_empty() {
+        message_fnames = [
+            "../test-data/at-the-mountains-of-madness.html",
+            "../test-data/dream-quest.html",
+            "../test-data/iso13818-2.pdf",
+        ]
+        .iter()
+        .map(|&str| str.to_string())
+        .collect()
+    }
+
+    println!("#\tbits\tstreaming\ttANS\tratio\trANS\tratio");
+    for fname in message_fnames {
+        println!("#\t{}", fname);
+        let message = slurp(&fname)?;
+        analyze(&message)?;
+    }
+
+    Ok(())
+}
+
+fn analyze(message: &[u8]) -> Result<(), Box<dyn Error>> {
+    println!("orig\t\t{}", message.len());
+
+    let mut raw = SymbolFrequencies::new();
+    raw.scan_file(&mut &message[..])?;
+
+    for &precision_bits in &[9, 11, 12, 14, 16] {
+        let normalized = raw.normalize(precision_bits, NormalizationStrategy::Greedy)?;
+        let freqs = normalized.frequencies.clone();
+
+        let ansu = StreamingANSUniform::try_new(freqs.clone(), 16, 2)?;
+        let streamed = ansu.encode_slice(message, 1)?;
+        assert!(ansu.decode(&streamed, 1)? == message, "streaming mismatch");
+
+        let tans = TabledANS::new(freqs, &SpreadMethod::Precise { phase: 0 })?;
+        let tabled = tans.encode(message)?;
+        assert!(tans.decode(&tabled)? == message, "tANS mismatch");
+
+        let rans = StreamingRANS::try_from_table(RANSTable::new(normalized.frequencies)?, 16, 2)?;
+        let ranged = rans.encode_slice(message, 1)?;
+        assert!(rans.decode(&ranged, 1)? == message, "rANS mismatch");
+
+        println!(
+            "\t{}\t{}\t{}\t{:.5}\t{}\t{:.5}",
+            precision_bits,
+            streamed.len(),
+            tabled.len(),
+            tabled.len() as f64 / streamed.len() as f64,
+            ranged.len(),
+            ranged.len() as f64 / streamed.len() as f64
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! The message and tables every benchmark runs on.
+
+use symbol_table::{ANSTableUniform, NormalizationStrategy, PackedANSTable, SymbolFrequencies};
+
+/// every byte, skewed towards the small ones like text is towards lowercase letters
+pub fn sample_message(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state as u8).min((state >> 8) as u8)
+        })
+        .collect()
+}
+
+/// the same table for `message` in both layouts
+pub fn tables(message: &[u8], precision_bits: u8) -> (ANSTableUniform, PackedANSTable) {
+    let mut raw = SymbolFrequencies::new();
+    raw.scan_file(&mut &message[..]).unwrap();
+    let freqs = raw
+        .normalize(precision_bits, NormalizationStrategy::Greedy)
+        .unwrap()
+        .frequencies;
+    let nested = ANSTableUniform::new(freqs);
+    let packed = PackedANSTable::from_table(&nested);
+    (nested, packed)
+}
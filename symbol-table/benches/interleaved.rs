@@ -0,0 +1,68 @@
+//! The single-state streaming coder versus `InterleavedANS` with 1, 2, 4 and 8 states, all on the
+//! packed table so only the dependency chain differs.
+//!
+//! cargo bench --bench interleaved
+
+mod common;
+
+use common::{sample_message, tables};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use symbol_table::{GenericInterleavedANS, StreamingANSPacked};
+
+const UNDERFLOW_BITS: u8 = 32;
+const BYTES_TO_STREAM: u8 = 2;
+
+fn bench_interleaved<const K: usize>(
+    group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>,
+    message: &[u8],
+    precision_bits: u8,
+) {
+    let coder = GenericInterleavedANS::<u8, 256, K, _>::from_table(
+        tables(message, precision_bits).1,
+        UNDERFLOW_BITS,
+        BYTES_TO_STREAM,
+    )
+    .unwrap();
+    let encoded = coder.encode(message).unwrap();
+    assert_eq!(message, &coder.decode(&encoded).unwrap()[..]);
+
+    let name = format!("{} states", K);
+    group.bench_function(
+        BenchmarkId::new(format!("encode {}", name), precision_bits),
+        |b| b.iter(|| coder.encode(black_box(message)).unwrap()),
+    );
+    group.bench_function(
+        BenchmarkId::new(format!("decode {}", name), precision_bits),
+        |b| b.iter(|| coder.decode(black_box(&encoded)).unwrap()),
+    );
+}
+
+fn streaming(c: &mut Criterion) {
+    let message = sample_message(1 << 18);
+
+    let mut group = c.benchmark_group("interleaved");
+    group.throughput(Throughput::Bytes(message.len() as u64));
+    for &precision_bits in &[12u8, 20] {
+        let single = StreamingANSPacked::from_table(
+            tables(&message, precision_bits).1,
+            UNDERFLOW_BITS,
+            BYTES_TO_STREAM,
+        );
+        let encoded = single.encode_slice(&message, 1).unwrap();
+        group.bench_function(BenchmarkId::new("encode single", precision_bits), |b| {
+            b.iter(|| single.encode_slice(black_box(&message), 1).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("decode single", precision_bits), |b| {
+            b.iter(|| single.decode(black_box(&encoded), 1).unwrap())
+        });
+
+        bench_interleaved::<1>(&mut group, &message, precision_bits);
+        bench_interleaved::<2>(&mut group, &message, precision_bits);
+        bench_interleaved::<4>(&mut group, &message, precision_bits);
+        bench_interleaved::<8>(&mut group, &message, precision_bits);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, streaming);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+//! Nested (`ANSTableUniform`) versus packed (`PackedANSTable`) tables.  The decode tables grow with the
+//! precision, so the gap between the layouts shows up once they stop fitting in L1, then L2.
+//!
+//! cargo bench --bench table_layout
+
+mod common;
+
+use common::{sample_message, tables};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use symbol_table::{StreamingANSPacked, StreamingANSUniform, UniformTable};
+
+fn decode_states<T: UniformTable<u8>>(table: &T, states: &[u64]) -> u64 {
+    states.iter().fold(0, |sum, &x| {
+        let (symbol, new_x) = table.decode64(x);
+        sum.wrapping_add(new_x + symbol as u64)
+    })
+}
+
+fn decode(c: &mut Criterion) {
+    let message = sample_message(1 << 16);
+    // states that land on unrelated slots, the worst case for the cache
+    let states: Vec<u64> = sample_message(1 << 15)
+        .chunks(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as u64 * 977)
+        .collect();
+
+    let mut group = c.benchmark_group("decode64");
+    group.throughput(Throughput::Elements(states.len() as u64));
+    for &precision_bits in &[10u8, 14, 18, 22] {
+        let (nested, packed) = tables(&message, precision_bits);
+        group.bench_with_input(
+            BenchmarkId::new("nested", precision_bits),
+            &states,
+            |b, states| b.iter(|| decode_states(&nested, black_box(states))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("packed", precision_bits),
+            &states,
+            |b, states| b.iter(|| decode_states(&packed, black_box(states))),
+        );
+    }
+    group.finish();
+}
+
+fn streaming(c: &mut Criterion) {
+    let message = sample_message(1 << 18);
+
+    let mut group = c.benchmark_group("streaming");
+    group.throughput(Throughput::Bytes(message.len() as u64));
+    for &precision_bits in &[12u8, 20] {
+        let (nested, packed) = tables(&message, precision_bits);
+        let nested = StreamingANSUniform::from_table(nested, 32, 2);
+        let packed = StreamingANSPacked::from_table(packed, 32, 2);
+        let encoded = nested.encode(message.iter().rev(), 1);
+
+        group.bench_function(BenchmarkId::new("encode nested", precision_bits), |b| {
+            b.iter(|| nested.encode(black_box(&message).iter().rev(), 1))
+        });
+        group.bench_function(BenchmarkId::new("encode packed", precision_bits), |b| {
+            b.iter(|| packed.encode(black_box(&message).iter().rev(), 1))
+        });
+        group.bench_function(BenchmarkId::new("decode nested", precision_bits), |b| {
+            b.iter(|| nested.decode(black_box(&encoded), 1).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("decode packed", precision_bits), |b| {
+            b.iter(|| packed.decode(black_box(&encoded), 1).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, streaming);
+criterion_main!(benches);
@@ -0,0 +1,154 @@
+//! Combining frequency tables from several corpora.  Every operation checks for u32 overflow instead of wrapping.
+
+use crate::GenericSymbolFrequencies;
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    /// the sum of all frequencies, which can exceed `u32`
+    pub fn total(&self) -> u64 {
+        self.frequencies.iter().map(|&f| f as u64).sum()
+    }
+
+    pub fn checked_add(
+        &self,
+        other: &GenericSymbolFrequencies<N>,
+    ) -> Result<GenericSymbolFrequencies<N>, String> {
+        let mut rval = self.clone();
+        for (symbol, (sum, &freq)) in rval
+            .frequencies
+            .iter_mut()
+            .zip(other.frequencies.iter())
+            .enumerate()
+        {
+            *sum = sum
+                .checked_add(freq)
+                .ok_or_else(|| format!("frequency of symbol {} overflows", symbol))?;
+        }
+        Ok(rval)
+    }
+
+    /// Remove the counts of `other`, which must not exceed ours for any symbol.
+    pub fn checked_sub(
+        &self,
+        other: &GenericSymbolFrequencies<N>,
+    ) -> Result<GenericSymbolFrequencies<N>, String> {
+        let mut rval = self.clone();
+        for (symbol, (difference, &freq)) in rval
+            .frequencies
+            .iter_mut()
+            .zip(other.frequencies.iter())
+            .enumerate()
+        {
+            *difference = difference.checked_sub(freq).ok_or_else(|| {
+                format!(
+                    "can not remove {} occurrences of symbol {}; there are only {}",
+                    freq, symbol, self.frequencies[symbol]
+                )
+            })?;
+        }
+        Ok(rval)
+    }
+
+    /// Multiply every frequency by `factor`, rounding to the nearest integer.
+    /// As long as `factor` is positive, symbols that were present keep a frequency of at least 1.
+    pub fn scale(&self, factor: f64) -> Result<GenericSymbolFrequencies<N>, String> {
+        if !factor.is_finite() || factor < 0.0 {
+            return Err(format!("can not scale frequencies by {}", factor));
+        }
+        let mut rval = GenericSymbolFrequencies::new();
+        for (symbol, (&freq, scaled)) in self
+            .frequencies
+            .iter()
+            .zip(rval.frequencies.iter_mut())
+            .enumerate()
+        {
+            *scaled = scaled_count(freq as f64 * factor, freq > 0 && factor > 0.0, symbol)?;
+        }
+        Ok(rval)
+    }
+
+    /// Blend the probability distributions of several tables.
+    /// A table's share of the result depends only on its weight, not on how many symbols it counted,
+    /// so `[(&html, 0.7), (&pdf, 0.3)]` is 70% HTML no matter how big the PDF corpus was.
+    ///
+    /// The result sums to approximately `total`; every symbol present in a table with a positive weight keeps a frequency of at least 1.
+    pub fn mix(
+        components: &[(&GenericSymbolFrequencies<N>, f64)],
+        total: u64,
+    ) -> Result<GenericSymbolFrequencies<N>, String> {
+        let mut sum_weights = 0.0;
+        for &(table, weight) in components {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("mixing weight {} is not allowed", weight));
+            }
+            if table.total() > 0 {
+                sum_weights += weight;
+            }
+        }
+        if sum_weights <= 0.0 {
+            return Err("nothing to mix: every table is empty or has no weight".to_string());
+        }
+
+        let mut probabilities = [0f64; N];
+        let mut present = [false; N];
+        for &(table, weight) in components {
+            let table_total = table.total();
+            if table_total == 0 || weight == 0.0 {
+                continue;
+            }
+            let share = weight / sum_weights / table_total as f64;
+            for (symbol, &freq) in table.frequencies.iter().enumerate() {
+                probabilities[symbol] += freq as f64 * share;
+                present[symbol] |= freq > 0;
+            }
+        }
+
+        let mut rval = GenericSymbolFrequencies::new();
+        for (symbol, mixed) in rval.frequencies.iter_mut().enumerate() {
+            *mixed = scaled_count(
+                probabilities[symbol] * total as f64,
+                present[symbol],
+                symbol,
+            )?;
+        }
+        Ok(rval)
+    }
+}
+
+fn scaled_count(value: f64, keep_present: bool, symbol: usize) -> Result<u32, String> {
+    let rounded = value.round();
+    if rounded > u32::MAX as f64 {
+        return Err(format!(
+            "frequency of symbol {} overflows ({})",
+            symbol, rounded
+        ));
+    }
+    let rval = rounded as u32;
+    Ok(if keep_present { rval.max(1) } else { rval })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SymbolFrequencies;
+
+    #[test]
+    fn algebra() {
+        let mut html = SymbolFrequencies::new();
+        html.frequencies[b'<' as usize] = 900;
+        html.frequencies[b'a' as usize] = 100;
+        let mut pdf = SymbolFrequencies::new();
+        pdf.frequencies[b'a' as usize] = 1_000_000;
+
+        let both = html.checked_add(&pdf).unwrap();
+        assert_eq!(1_001_000, both.total());
+        assert_eq!(
+            &html.frequencies[..],
+            &both.checked_sub(&pdf).unwrap().frequencies[..]
+        );
+        assert!(html.checked_sub(&pdf).is_err());
+        assert!(pdf.scale(5000.0).is_err());
+
+        let mixed = SymbolFrequencies::mix(&[(&html, 0.7), (&pdf, 0.3)], 1000).unwrap();
+        assert_eq!(630, mixed.frequencies[b'<' as usize]);
+        assert_eq!(370, mixed.frequencies[b'a' as usize]);
+    }
+}
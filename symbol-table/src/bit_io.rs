@@ -0,0 +1,111 @@
+//! MSB-first bit packing on top of `Write` and `Read`
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+pub struct BitWriter<'a> {
+    sink: &'a mut dyn Write,
+    accum: u8,
+    accum_bits: u8,
+    pub bits_written: u64,
+}
+
+impl<'a> BitWriter<'a> {
+    pub fn new(sink: &'a mut dyn Write) -> BitWriter<'a> {
+        BitWriter {
+            sink,
+            accum: 0,
+            accum_bits: 0,
+            bits_written: 0,
+        }
+    }
+
+    /// write the low `num_bits` of `val`, most significant first
+    pub fn write_bits(&mut self, val: u64, num_bits: u8) -> Result<(), Error> {
+        for i in (0..num_bits).rev() {
+            self.accum = (self.accum << 1) | ((val >> i) & 1) as u8;
+            self.accum_bits += 1;
+            if self.accum_bits == 8 {
+                self.sink.write_all(&[self.accum])?;
+                self.accum = 0;
+                self.accum_bits = 0;
+            }
+        }
+        self.bits_written += num_bits as u64;
+        Ok(())
+    }
+
+    /// Elias gamma code; `val` must not be 0
+    pub fn write_gamma(&mut self, val: u64) -> Result<(), Error> {
+        let num_bits = bit_length(val);
+        self.write_bits(0, num_bits - 1)?;
+        self.write_bits(val, num_bits)
+    }
+
+    /// pad the final partial byte with zeros
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.accum_bits > 0 {
+            let padding = 8 - self.accum_bits;
+            self.sink.write_all(&[self.accum << padding])?;
+            self.accum = 0;
+            self.accum_bits = 0;
+        }
+        Ok(())
+    }
+}
+
+//
+
+pub struct BitReader<'a> {
+    src: &'a mut dyn Read,
+    accum: u8,
+    accum_bits: u8,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(src: &'a mut dyn Read) -> BitReader<'a> {
+        BitReader {
+            src,
+            accum: 0,
+            accum_bits: 0,
+        }
+    }
+
+    pub fn read_bits(&mut self, num_bits: u8) -> Result<u64, Error> {
+        let mut rval = 0u64;
+        for _ in 0..num_bits {
+            if self.accum_bits == 0 {
+                let mut byte = [0u8];
+                self.src.read_exact(&mut byte)?;
+                self.accum = byte[0];
+                self.accum_bits = 8;
+            }
+            self.accum_bits -= 1;
+            rval = (rval << 1) | ((self.accum >> self.accum_bits) & 1) as u64;
+        }
+        Ok(rval)
+    }
+
+    pub fn read_gamma(&mut self) -> Result<u64, Error> {
+        let mut zeros = 0u8;
+        while self.read_bits(1)? == 0 {
+            zeros += 1;
+            if zeros >= 64 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "malformed Elias gamma code",
+                ));
+            }
+        }
+        Ok((1 << zeros) | self.read_bits(zeros)?)
+    }
+}
+
+/// number of bits needed to represent `val` (0 for 0)
+pub fn bit_length(val: u64) -> u8 {
+    (64 - val.leading_zeros()) as u8
+}
+
+/// number of bits in the Elias gamma code for `val`
+pub fn gamma_length(val: u64) -> u64 {
+    2 * bit_length(val) as u64 - 1
+}
@@ -0,0 +1,282 @@
+//! Compact serialization of `SymbolFrequencies` for shipping a table in front of a compressed payload.
+//!
+//! This borrows the ideas of zstd's FSE normalized-count header.  The bit stream (MSB first) is
+//!
+//! * 6 bits: normalization precision `p`, or 0 for raw counts
+//! * 1 bit: 0 if a presence bitmap (one bit per symbol of the alphabet) follows, 1 if a run list follows.
+//!   A run list alternates absent and present runs (starting with absent),
+//!   each written as the Elias gamma code of `length+1`, until the whole alphabet is covered.
+//! * the counts of the present symbols, in symbol order:
+//!   * raw counts are Elias gamma coded
+//!   * normalized counts are written as `count-1` using just enough bits to hold the largest count still possible
+//!     given the remaining probability mass.  The last count is implied by the remainder.
+//!
+//! The stream is padded with zero bits to a whole byte.
+
+use crate::bit_io::{bit_length, gamma_length, BitReader, BitWriter};
+use crate::GenericSymbolFrequencies;
+use std::io::{sink, Error, ErrorKind, Read, Write};
+
+const PRECISION_FIELD_BITS: u8 = 6;
+const MAX_PRECISION_BITS: u8 = 32;
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    /// Write the compact form of this table and return how many bits of it were significant
+    /// (before padding to a whole byte).
+    ///
+    /// Pass `Some(precision_bits)` if the frequencies have been normalized to sum to `1<<precision_bits`;
+    /// normalized tables are smaller because the last count is implied.
+    pub fn write_compact(
+        &self,
+        sink: &mut dyn Write,
+        precision_bits: Option<u8>,
+    ) -> Result<u64, Error> {
+        let mut bits = BitWriter::new(sink);
+        self.write_compact_bits(&mut bits, precision_bits)?;
+        bits.flush()?;
+        Ok(bits.bits_written)
+    }
+
+    /// How many bits `write_compact` would emit, not counting the padding to a whole byte.
+    /// Use this to weigh the cost of shipping a table against the bits it saves.
+    pub fn compact_size_bits(&self, precision_bits: Option<u8>) -> Result<u64, Error> {
+        let mut discard = sink();
+        let mut bits = BitWriter::new(&mut discard);
+        self.write_compact_bits(&mut bits, precision_bits)?;
+        Ok(bits.bits_written)
+    }
+
+    fn write_compact_bits(
+        &self,
+        bits: &mut BitWriter,
+        precision_bits: Option<u8>,
+    ) -> Result<(), Error> {
+        if let Some(p) = precision_bits {
+            let total: u64 = self.frequencies.iter().map(|&f| f as u64).sum();
+            if p == 0 || p > MAX_PRECISION_BITS || total != 1 << p {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "frequencies sum to {}, which is not a {}-bit normalization",
+                        total, p
+                    ),
+                ));
+            }
+        }
+        bits.write_bits(precision_bits.unwrap_or(0) as u64, PRECISION_FIELD_BITS)?;
+
+        let runs = presence_runs(&self.frequencies);
+        let run_list_bits: u64 = runs.iter().map(|&len| gamma_length(len + 1)).sum();
+        if run_list_bits < self.frequencies.len() as u64 {
+            bits.write_bits(1, 1)?;
+            for &len in &runs {
+                bits.write_gamma(len + 1)?;
+            }
+        } else {
+            bits.write_bits(0, 1)?;
+            for &freq in self.frequencies.iter() {
+                bits.write_bits((freq > 0) as u64, 1)?;
+            }
+        }
+
+        let present: Vec<u32> = self
+            .frequencies
+            .iter()
+            .cloned()
+            .filter(|&f| f > 0)
+            .collect();
+        match precision_bits {
+            None => {
+                for &freq in &present {
+                    bits.write_gamma(freq as u64)?;
+                }
+            }
+            Some(p) => {
+                let mut remaining = 1u64 << p;
+                for (i, &freq) in present.iter().enumerate() {
+                    let still_to_come = (present.len() - i - 1) as u64;
+                    if still_to_come == 0 {
+                        break;
+                    }
+                    let max = remaining - still_to_come;
+                    bits.write_bits(freq as u64 - 1, bit_length(max - 1))?;
+                    remaining -= freq as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a table written by `write_compact`.  Returns the table and its normalization precision (if any).
+    /// Reads exactly the bytes `write_compact` wrote, so the payload that follows is left unread in `src`.
+    pub fn parse_compact(
+        src: &mut dyn Read,
+    ) -> Result<(GenericSymbolFrequencies<N>, Option<u8>), Error> {
+        let mut bits = BitReader::new(src);
+        let precision_bits = match bits.read_bits(PRECISION_FIELD_BITS)? as u8 {
+            0 => None,
+            p if p > MAX_PRECISION_BITS => {
+                return Err(invalid_data(format!(
+                    "compact table precision {} exceeds {}",
+                    p, MAX_PRECISION_BITS
+                )))
+            }
+            p => Some(p),
+        };
+
+        let mut rval = GenericSymbolFrequencies::<N>::new();
+        let alphabet_size = rval.frequencies.len();
+        let mut present = Vec::new();
+        if bits.read_bits(1)? == 1 {
+            let mut cursor = 0usize;
+            let mut is_present = false;
+            while cursor < alphabet_size {
+                let len = (bits.read_gamma()? - 1) as usize;
+                if len > alphabet_size - cursor {
+                    return Err(invalid_data(format!(
+                        "compact table run of {} overflows the alphabet at symbol {}",
+                        len, cursor
+                    )));
+                }
+                if is_present {
+                    present.extend(cursor..cursor + len);
+                }
+                cursor += len;
+                is_present = !is_present;
+            }
+        } else {
+            for symbol in 0..alphabet_size {
+                if bits.read_bits(1)? == 1 {
+                    present.push(symbol);
+                }
+            }
+        }
+
+        match precision_bits {
+            None => {
+                for &symbol in &present {
+                    let freq = bits.read_gamma()?;
+                    if freq > u32::MAX as u64 {
+                        return Err(invalid_data(format!(
+                            "compact table count {} for symbol {} does not fit in 32 bits",
+                            freq, symbol
+                        )));
+                    }
+                    rval.frequencies[symbol] = freq as u32;
+                }
+            }
+            Some(_) if present.is_empty() => {
+                return Err(invalid_data(
+                    "normalized compact table has no symbols".to_string(),
+                ))
+            }
+            Some(p) => {
+                let mut remaining = 1u64 << p;
+                if (present.len() as u64) > remaining {
+                    return Err(invalid_data(format!(
+                        "compact table has {} symbols but only {} slots",
+                        present.len(),
+                        remaining
+                    )));
+                }
+                for (i, &symbol) in present.iter().enumerate() {
+                    let still_to_come = (present.len() - i - 1) as u64;
+                    let freq = if still_to_come == 0 {
+                        remaining
+                    } else {
+                        let max = remaining - still_to_come;
+                        let freq = bits.read_bits(bit_length(max - 1))? + 1;
+                        if freq > max {
+                            return Err(invalid_data(format!(
+                                "compact table count {} for symbol {} exceeds the remaining {}",
+                                freq, symbol, max
+                            )));
+                        }
+                        freq
+                    };
+                    rval.frequencies[symbol] = freq as u32;
+                    remaining -= freq;
+                }
+            }
+        }
+
+        Ok((rval, precision_bits))
+    }
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// lengths of alternating absent/present runs, starting with absent.  Lengths are never 0 except possibly the first.
+fn presence_runs(frequencies: &[u32]) -> Vec<u64> {
+    let mut runs = vec![0u64];
+    let mut is_present = false;
+    for &freq in frequencies {
+        if (freq > 0) != is_present {
+            runs.push(0);
+            is_present = !is_present;
+        }
+        *runs.last_mut().unwrap() += 1;
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GenericSymbolFrequencies, SymbolFrequencies};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn compact_round_trip() {
+        let mut sparse = SymbolFrequencies::new();
+        for &(symbol, freq) in &[(b'a', 600), (b'b', 300), (b'c', 100), (b'z', 24)] {
+            sparse.frequencies[symbol as usize] = freq;
+        }
+        let mut dense = SymbolFrequencies::new();
+        for (symbol, freq) in dense.frequencies.iter_mut().enumerate() {
+            *freq = 1 + (symbol as u32 % 7) * 1000;
+        }
+
+        for (freqs, precision_bits) in &[(sparse, Some(10)), (dense, None)] {
+            let mut buffer = Vec::new();
+            let bits = freqs.write_compact(&mut buffer, *precision_bits).unwrap();
+            assert_eq!(bits, freqs.compact_size_bits(*precision_bits).unwrap());
+            assert_eq!((bits as usize).div_ceil(8), buffer.len());
+
+            buffer.push(0xAA);
+            let mut src = &buffer[..];
+            let (parsed, parsed_precision) = SymbolFrequencies::parse_compact(&mut src).unwrap();
+            assert_eq!(*precision_bits, parsed_precision);
+            assert_eq!(&freqs.frequencies[..], &parsed.frequencies[..]);
+            assert_eq!(&[0xAA], src);
+        }
+    }
+
+    #[test]
+    fn compact_wide_alphabet() {
+        let mut freqs = GenericSymbolFrequencies::<4096>::new();
+        freqs.add_symbols((0..20000u32).map(|i| (i * i) % 4001));
+        let mut buffer = Vec::new();
+        freqs.write_compact(&mut buffer, None).unwrap();
+        let (parsed, _) =
+            GenericSymbolFrequencies::<4096>::parse_compact(&mut &buffer[..]).unwrap();
+        assert_eq!(&freqs.frequencies[..], &parsed.frequencies[..]);
+    }
+
+    #[test]
+    fn compact_normalized_without_symbols() {
+        // precision 10, then one absent run over all 256 symbols
+        let header = [0x2A, 0x01, 0x01];
+        let err = SymbolFrequencies::parse_compact(&mut &header[..])
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+
+        // the same header without a precision is a valid empty table of counts
+        let header = [0x02, 0x01, 0x01];
+        let (parsed, precision_bits) = SymbolFrequencies::parse_compact(&mut &header[..]).unwrap();
+        assert_eq!(None, precision_bits);
+        assert_eq!(0, parsed.total());
+    }
+}
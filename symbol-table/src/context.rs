@@ -0,0 +1,392 @@
+//! Order-1 context modeling: the frequency table for each symbol is chosen by the byte before it.
+
+use crate::bit_io::{BitReader, BitWriter};
+use crate::streaming::{DecodeSteps, EncodeFailure, StreamLoop};
+use crate::{
+    count_overflow, ANSTableUniform, AnsError, Diagnostics, NormalizationStrategy, StateWord,
+    StreamingANSUniform, SymbolFrequencies,
+};
+use std::io::{Error, Read, Write};
+
+/// the context used for the first symbol of a message, which has no predecessor
+pub const INITIAL_CONTEXT: u8 = 0;
+
+/// One `SymbolFrequencies` per possible previous byte.
+#[derive(Clone)]
+pub struct ContextFrequencies {
+    pub contexts: Vec<SymbolFrequencies>,
+}
+
+impl ContextFrequencies {
+    pub fn new() -> ContextFrequencies {
+        ContextFrequencies {
+            contexts: (0..256).map(|_| SymbolFrequencies::new()).collect(),
+        }
+    }
+
+    /// Count each byte of `f` in the context of the byte before it.
+    /// The first byte is counted in `INITIAL_CONTEXT`.
+    /// Like `SymbolFrequencies::scan_file`, this fails rather than let a count wrap.
+    pub fn scan_file(&mut self, f: &mut dyn Read) -> Result<(), Error> {
+        let mut buffer = [0; 4 << 10];
+        let mut context = INITIAL_CONTEXT;
+
+        loop {
+            let count = f.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            for &symbol in &buffer[..count] {
+                let freq = &mut self.contexts[context as usize].frequencies[symbol as usize];
+                *freq = freq.checked_add(1).ok_or_else(|| {
+                    count_overflow(
+                        symbol,
+                        "scan a smaller sample of the input for context tables",
+                    )
+                })?;
+                context = symbol;
+            }
+        }
+        Ok(())
+    }
+
+    /// the order-0 table you would have gotten from `SymbolFrequencies::scan_file`, which fails rather
+    /// than let a sum wrap
+    pub fn order0(&self) -> Result<SymbolFrequencies, String> {
+        self.contexts
+            .iter()
+            .try_fold(SymbolFrequencies::new(), |sum, context| {
+                sum.checked_add(context)
+            })
+    }
+
+    pub fn checked_add(&self, other: &ContextFrequencies) -> Result<ContextFrequencies, String> {
+        let contexts = self
+            .contexts
+            .iter()
+            .zip(other.contexts.iter())
+            .map(|(a, b)| a.checked_add(b))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(ContextFrequencies { contexts })
+    }
+
+    /// H(X | previous byte) in bits per symbol
+    pub fn conditional_entropy(&self) -> f64 {
+        let total: u64 = self.contexts.iter().map(|context| context.total()).sum();
+        if total == 0 {
+            return 0.0;
+        }
+        self.contexts
+            .iter()
+            .map(|context| context.total() as f64 / total as f64 * context.entropy())
+            .sum()
+    }
+
+    /// Normalize every non-empty context to `1<<precision_bits`.  Empty contexts stay empty.
+    pub fn normalize(
+        &self,
+        precision_bits: u8,
+        strategy: NormalizationStrategy,
+    ) -> Result<ContextFrequencies, String> {
+        let contexts = self
+            .contexts
+            .iter()
+            .map(|context| {
+                if context.total() == 0 {
+                    Ok(SymbolFrequencies::new())
+                } else {
+                    Ok(context.normalize(precision_bits, strategy)?.frequencies)
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(ContextFrequencies { contexts })
+    }
+
+    /// A 256-bit map of the non-empty contexts followed by the `write_compact` form of each of them.
+    pub fn write_context_tables(
+        &self,
+        sink: &mut dyn Write,
+        precision_bits: Option<u8>,
+    ) -> Result<(), Error> {
+        {
+            let mut bits = BitWriter::new(&mut *sink);
+            for context in &self.contexts {
+                bits.write_bits((context.total() > 0) as u64, 1)?;
+            }
+            bits.flush()?;
+        }
+        for context in self.contexts.iter().filter(|c| c.total() > 0) {
+            context.write_compact(sink, precision_bits)?;
+        }
+        Ok(())
+    }
+
+    /// Parse the output of `write_context_tables`.  The precision is `None` unless every context was normalized to the same one.
+    pub fn parse_context_tables(
+        src: &mut dyn Read,
+    ) -> Result<(ContextFrequencies, Option<u8>), Error> {
+        let mut present = Vec::new();
+        {
+            let mut bits = BitReader::new(&mut *src);
+            for _ in 0..256 {
+                present.push(bits.read_bits(1)? == 1);
+            }
+        }
+
+        let mut rval = ContextFrequencies::new();
+        let mut precisions = Vec::new();
+        for (context, _) in present.iter().enumerate().filter(|(_, &p)| p) {
+            let (freqs, precision_bits) = SymbolFrequencies::parse_compact(src)?;
+            rval.contexts[context] = freqs;
+            precisions.push(precision_bits);
+        }
+        let precision_bits = match precisions.first() {
+            Some(&first) if precisions.iter().all(|&p| p == first) => first,
+            _ => None,
+        };
+        Ok((rval, precision_bits))
+    }
+}
+
+impl Default for ContextFrequencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+//
+//
+
+/// A streaming coder like `StreamingANSUniform`, but every symbol is coded with the table for the byte that precedes it.
+pub struct StreamingANSContext {
+    tables: Vec<ANSTableUniform>,
+    /// index into `tables` for each context.  Contexts that never occurred share the order-0 table.
+    table_for_context: Vec<usize>,
+    sum_frequencies: u32,
+    pub underflow_bits: u8,
+    pub bytes_to_stream: u8,
+    pub verbose: bool,
+    pub diagnostics: Diagnostics,
+}
+
+impl StreamingANSContext {
+    /// Every context is normalized to `1<<precision_bits`.  Keep `precision_bits` modest (12 is plenty);
+    /// each occupied context builds its own `ANSTableUniform`.
+    ///
+    /// A symbol can only be encoded if it occurred in its context (or, for contexts with no statistics, anywhere).
+    pub fn new(
+        contexts: &ContextFrequencies,
+        precision_bits: u8,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<StreamingANSContext, AnsError> {
+        Self::with_diagnostics(
+            contexts,
+            precision_bits,
+            underflow_bits,
+            bytes_to_stream,
+            Diagnostics::default(),
+        )
+    }
+
+    /// `new`, with the warnings about every context's table sent to `diagnostics`
+    pub fn with_diagnostics(
+        contexts: &ContextFrequencies,
+        precision_bits: u8,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+        diagnostics: Diagnostics,
+    ) -> Result<StreamingANSContext, AnsError> {
+        let strategy = NormalizationStrategy::Greedy;
+        let build_table = |freqs: &SymbolFrequencies| {
+            let normalized = with_spare_slot(freqs)
+                .normalize(precision_bits, strategy)
+                .map_err(AnsError::InvalidTable)?;
+            let mut table = ANSTableUniform::new(normalized.frequencies);
+            table.diagnostics = diagnostics.clone();
+            StreamingANSUniform::check_balance(&table, underflow_bits, bytes_to_stream)?;
+            Ok(table)
+        };
+
+        let order0 = contexts.order0().map_err(AnsError::InvalidTable)?;
+        let mut tables = vec![build_table(&order0)?];
+        let mut table_for_context = Vec::new();
+        for context in &contexts.contexts {
+            if context.total() == 0 {
+                table_for_context.push(0);
+            } else {
+                table_for_context.push(tables.len());
+                tables.push(build_table(context)?);
+            }
+        }
+
+        Ok(StreamingANSContext {
+            tables,
+            table_for_context,
+            sum_frequencies: 1 << precision_bits,
+            underflow_bits,
+            bytes_to_stream,
+            verbose: false,
+            diagnostics,
+        })
+    }
+
+    fn table(&self, context: u8) -> &ANSTableUniform {
+        &self.tables[self.table_for_context[context as usize]]
+    }
+
+    fn stream_loop(&self) -> StreamLoop<'_> {
+        StreamLoop {
+            underflow_bits: self.underflow_bits,
+            quantum_bits: 8 * self.bytes_to_stream,
+            trace: Some(&self.diagnostics).filter(|_| self.verbose),
+        }
+    }
+
+    /// Unlike `StreamingANSUniform::encode`, `message` is in its natural order;
+    /// the contexts have to be looked up before the symbols are fed to the coder backwards.
+    ///
+    /// For `initial_value` you probably want `1`, and you absolutely do not want `0`.
+    ///
+    /// Panics on a symbol that is not in its context's table; see `try_encode`
+    pub fn encode(&self, message: &[u8], initial_value: u64) -> Vec<u8> {
+        self.try_encode(message, initial_value)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_encode(&self, message: &[u8], initial_value: u64) -> Result<Vec<u8>, AnsError> {
+        let initial_state = self
+            .stream_loop()
+            .initial_state(initial_value, self.sum_frequencies)?;
+        // (context, symbol) pairs, last symbol first
+        let steps = (0..message.len()).rev().map(|i| {
+            let context = if i == 0 {
+                INITIAL_CONTEXT
+            } else {
+                message[i - 1]
+            };
+            (context, message[i])
+        });
+
+        let mut rval = Vec::new();
+        self.stream_loop()
+            .encode(
+                steps,
+                |x: u64, &(context, symbol)| x.append_encode(self.table(context), symbol),
+                initial_state,
+                &mut |byte| {
+                    rval.push(byte);
+                    Ok(())
+                },
+            )
+            .map_err(|failure| match failure {
+                EncodeFailure::Sink(e) | EncodeFailure::Coder(e) => e,
+            })?;
+        Ok(rval)
+    }
+
+    /// `eos_marker` is the same value passed to `encode()` as `initial_value`
+    pub fn decode(&self, stream: &[u8], eos_marker: u64) -> Result<Vec<u8>, AnsError> {
+        let eos_state = self
+            .stream_loop()
+            .initial_state::<u64>(eos_marker, self.sum_frequencies)?;
+        let mut steps = ContextSteps {
+            coder: self,
+            context: INITIAL_CONTEXT,
+            out: Vec::new(),
+        };
+        self.stream_loop().decode(stream, eos_state, &mut steps)?;
+        Ok(steps.out)
+    }
+}
+
+/// A table whose only symbol owns every slot codes that symbol without changing the state, so the
+/// decoder could not tell how many of them there were.  Such a context gets one spare count for the
+/// byte after its symbol.
+fn with_spare_slot(freqs: &SymbolFrequencies) -> SymbolFrequencies {
+    let mut rval = freqs.clone();
+    let mut present = (0..256).filter(|&symbol| freqs.frequencies[symbol] > 0);
+    if let (Some(only), None) = (present.next(), present.next()) {
+        rval.frequencies[(only + 1) % 256] = 1;
+    }
+    rval
+}
+
+/// `DecodeSteps` that decode each symbol with the table for the symbol before it
+struct ContextSteps<'a> {
+    coder: &'a StreamingANSContext,
+    context: u8,
+    out: Vec<u8>,
+}
+
+impl DecodeSteps<u64> for ContextSteps<'_> {
+    type Symbol = u8;
+
+    fn decode(&mut self, x: u64) -> Result<(u8, u64), AnsError> {
+        let (symbol, new_x) = x.decode_step(self.coder.table(self.context));
+        self.out.push(symbol);
+        self.context = symbol;
+        Ok((symbol, new_x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ContextFrequencies, NormalizationStrategy, StreamingANSContext};
+
+    #[test]
+    fn context_round_trip() {
+        let message: Vec<u8> = b"the theme of the thesis is that the thin thread is thick"
+            .iter()
+            .cycle()
+            .take(5000)
+            .cloned()
+            .collect();
+        let mut contexts = ContextFrequencies::new();
+        contexts.scan_file(&mut &message[..]).unwrap();
+        assert!(contexts.conditional_entropy() < 1.5);
+
+        let normalized = contexts
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap();
+        let mut serialized = Vec::new();
+        normalized
+            .write_context_tables(&mut serialized, Some(12))
+            .unwrap();
+        let (parsed, precision_bits) =
+            ContextFrequencies::parse_context_tables(&mut &serialized[..]).unwrap();
+        assert_eq!(Some(12), precision_bits);
+        for (a, b) in parsed.contexts.iter().zip(normalized.contexts.iter()) {
+            assert_eq!(&a.frequencies[..], &b.frequencies[..]);
+        }
+
+        let coder = StreamingANSContext::new(&parsed, 12, 16, 2).unwrap();
+        let encoded = coder.encode(&message, 1);
+        assert_eq!(message, coder.decode(&encoded, 1).unwrap());
+    }
+
+    #[test]
+    fn order0_refuses_to_wrap() {
+        let mut contexts = ContextFrequencies::new();
+        contexts.contexts[1].frequencies[b'a' as usize] = u32::MAX;
+        assert_eq!(
+            u32::MAX,
+            contexts.order0().unwrap().frequencies[b'a' as usize]
+        );
+        contexts.contexts[2].frequencies[b'a' as usize] = 1;
+        assert!(contexts.order0().is_err());
+    }
+
+    #[test]
+    fn short_messages_with_one_symbol_contexts() {
+        // every context here predicts its next byte with certainty
+        for message in [&b"T"[..], b"Th", b"The", b"aaaa", b"abababab"] {
+            let mut contexts = ContextFrequencies::new();
+            contexts.scan_file(&mut &message[..]).unwrap();
+            let coder = StreamingANSContext::new(&contexts, 12, 16, 2).unwrap();
+            let encoded = coder.encode(message, 1);
+            assert_eq!(message, &coder.decode(&encoded, 1).unwrap()[..]);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! 64-bit symbol counts for inputs too big for the `u32` frequencies of `SymbolFrequencies`.
+//!
+//! Count into a `SymbolCounts`, then `downscale` it into a table.  `normalize` works on the downscaled
+//! table just as well, because it only cares about the ratios.
+
+use crate::GenericSymbolFrequencies;
+use std::convert::TryFrom;
+use std::io::{Error, Read};
+
+/// Counts for an alphabet of `N` symbols that will not overflow for any file that fits on a disk.
+#[derive(Clone)]
+pub struct GenericSymbolCounts<const N: usize> {
+    pub counts: [u64; N],
+}
+
+pub type SymbolCounts = GenericSymbolCounts<256>;
+
+/// the result of `GenericSymbolCounts::downscale`
+pub struct DownscaledFrequencies<const N: usize = 256> {
+    pub frequencies: GenericSymbolFrequencies<N>,
+    /// every count was divided by `1<<shift` (rounding to nearest); 0 means the counts were copied exactly
+    pub shift: u8,
+}
+
+impl<const N: usize> GenericSymbolCounts<N> {
+    pub fn new() -> GenericSymbolCounts<N> {
+        GenericSymbolCounts { counts: [0; N] }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn checked_add(
+        &self,
+        other: &GenericSymbolCounts<N>,
+    ) -> Result<GenericSymbolCounts<N>, String> {
+        let mut rval = self.clone();
+        for (symbol, (sum, &count)) in rval.counts.iter_mut().zip(other.counts.iter()).enumerate() {
+            *sum = sum
+                .checked_add(count)
+                .ok_or_else(|| format!("count of symbol {} overflows", symbol))?;
+        }
+        Ok(rval)
+    }
+
+    /// Multiply every count by `factor`, rounding to the nearest integer.
+    /// Like `GenericSymbolFrequencies::scale`, present symbols keep a count of at least 1.
+    pub fn scale(&self, factor: f64) -> Result<GenericSymbolCounts<N>, String> {
+        if !factor.is_finite() || factor < 0.0 {
+            return Err(format!("can not scale counts by {}", factor));
+        }
+        let mut rval = GenericSymbolCounts::new();
+        for (symbol, (&count, scaled)) in self.counts.iter().zip(rval.counts.iter_mut()).enumerate()
+        {
+            let rounded = (count as f64 * factor).round();
+            if rounded >= u64::MAX as f64 {
+                return Err(format!(
+                    "count of symbol {} overflows ({})",
+                    symbol, rounded
+                ));
+            }
+            *scaled = rounded as u64;
+            if count > 0 && factor > 0.0 {
+                *scaled = (*scaled).max(1);
+            }
+        }
+        Ok(rval)
+    }
+
+    /// The exact table, or an error if any count is too big for a `u32`.
+    pub fn to_frequencies(&self) -> Result<GenericSymbolFrequencies<N>, String> {
+        let mut rval = GenericSymbolFrequencies::new();
+        for (symbol, (&count, freq)) in self
+            .counts
+            .iter()
+            .zip(rval.frequencies.iter_mut())
+            .enumerate()
+        {
+            *freq = u32::try_from(count).map_err(|_| {
+                format!(
+                    "count {} of symbol {} does not fit in a frequency table; downscale it",
+                    count, symbol
+                )
+            })?;
+        }
+        Ok(rval)
+    }
+
+    /// Divide the counts by the smallest power of two that makes their sum fit in a `u32`, so the
+    /// result can go straight into a table.  Symbols that were present keep a frequency of at least 1.
+    pub fn downscale(&self) -> DownscaledFrequencies<N> {
+        let mut shift = 0;
+        while self.downscaled_total(shift) > u32::MAX as u128 {
+            shift += 1;
+        }
+
+        let mut frequencies = GenericSymbolFrequencies::new();
+        for (&count, freq) in self.counts.iter().zip(frequencies.frequencies.iter_mut()) {
+            *freq = downscaled_count(count, shift) as u32;
+        }
+        DownscaledFrequencies { frequencies, shift }
+    }
+
+    fn downscaled_total(&self, shift: u8) -> u128 {
+        self.counts
+            .iter()
+            .map(|&count| downscaled_count(count, shift))
+            .sum()
+    }
+}
+
+/// `count` divided by `1<<shift`, rounding to nearest, but at least 1 if `count` was not 0
+fn downscaled_count(count: u64, shift: u8) -> u128 {
+    let count = count as u128;
+    if count == 0 || shift == 0 {
+        count
+    } else {
+        ((count + (1 << (shift - 1))) >> shift).max(1)
+    }
+}
+
+impl SymbolCounts {
+    pub fn scan_file(&mut self, f: &mut dyn Read) -> Result<(), Error> {
+        let mut buffer = [0; 4 << 10];
+
+        loop {
+            let count = f.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            for &symbol in &buffer[..count] {
+                self.counts[symbol as usize] += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for GenericSymbolCounts<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> From<&GenericSymbolFrequencies<N>> for GenericSymbolCounts<N> {
+    fn from(freqs: &GenericSymbolFrequencies<N>) -> Self {
+        let mut rval = GenericSymbolCounts::new();
+        for (count, &freq) in rval.counts.iter_mut().zip(freqs.frequencies.iter()) {
+            *count = freq as u64;
+        }
+        rval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolCounts, SymbolFrequencies};
+
+    #[test]
+    fn downscale() {
+        let mut counts = SymbolCounts::new();
+        counts.counts[b'a' as usize] = 5 << 32;
+        counts.counts[b'b' as usize] = 3 << 31;
+        counts.counts[b'c' as usize] = 1;
+        assert!(counts.to_frequencies().is_err());
+
+        let downscaled = counts.downscale();
+        assert_eq!(3, downscaled.shift);
+        assert_eq!(5 << 29, downscaled.frequencies.frequencies[b'a' as usize]);
+        assert_eq!(3 << 28, downscaled.frequencies.frequencies[b'b' as usize]);
+        assert_eq!(1, downscaled.frequencies.frequencies[b'c' as usize]);
+
+        let mut small = SymbolFrequencies::new();
+        small.frequencies[7] = u32::MAX;
+        let counts = SymbolCounts::from(&small);
+        assert_eq!(0, counts.downscale().shift);
+        assert_eq!(
+            &small.frequencies[..],
+            &counts.to_frequencies().unwrap().frequencies[..]
+        );
+
+        // each count fits in a u32, but their sum does not
+        let mut counts = SymbolCounts::new();
+        for count in &mut counts.counts[..4] {
+            *count = u32::MAX as u64 - 1;
+        }
+        counts.counts[4] = 1;
+        let downscaled = counts.downscale();
+        assert_eq!(3, downscaled.shift);
+        let total: u64 = downscaled
+            .frequencies
+            .frequencies
+            .iter()
+            .map(|&f| f as u64)
+            .sum();
+        assert_eq!(4 << 29 | 1, total);
+        assert_eq!(1, downscaled.frequencies.frequencies[4]);
+    }
+}
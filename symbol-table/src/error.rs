@@ -0,0 +1,175 @@
+//! Errors from building and running the coders, and the hook that receives their diagnostics.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnsError {
+    /// `initial_value` or `eos_marker` was 0, which the coders can not represent
+    ZeroInitialValue,
+    /// the message contains a symbol whose frequency is 0
+    SymbolNotInTable { symbol: usize },
+    /// the encode and decode tables are inconsistent with the frequencies
+    InvalidTable(String),
+    /// `underflow_bits` and `bytes_to_stream` do not leave room for the table
+    Unbalanced(String),
+    /// the stream ended without decoding back to the initial state; it is truncated, corrupt,
+    /// or was encoded with a different table or initial value
+    EosNotReached,
+    /// `encode_into` or `decode_into` ran out of room in a buffer of `capacity` elements
+    OutputTooSmall { capacity: usize },
+}
+
+impl Display for AnsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AnsError::ZeroInitialValue => write!(f, "the initial value must not be 0"),
+            AnsError::SymbolNotInTable { symbol } => {
+                write!(f, "symbol {} does not appear in symbol table", symbol)
+            }
+            AnsError::InvalidTable(msg) => write!(f, "invalid table: {}", msg),
+            AnsError::Unbalanced(msg) => write!(f, "unbalanced stream parameters: {}", msg),
+            AnsError::EosNotReached => write!(f, "failed to reach EOS marker"),
+            AnsError::OutputTooSmall { capacity } => {
+                write!(f, "output buffer of {} elements is too small", capacity)
+            }
+        }
+    }
+}
+
+impl Error for AnsError {}
+
+/// so the `io::Read` and `io::Write` adapters can report coder errors as `InvalidData`
+impl From<AnsError> for std::io::Error {
+    fn from(e: AnsError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Receives warnings, and the traces the coders produce when `verbose` is set.
+/// The default discards them; install `Diagnostics::stdout()` or your own hook to see them.
+#[derive(Clone)]
+pub struct Diagnostics(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl Diagnostics {
+    pub fn new<F: Fn(&str) + Send + Sync + 'static>(hook: F) -> Diagnostics {
+        Diagnostics(Arc::new(hook))
+    }
+
+    /// discard everything
+    pub fn silent() -> Diagnostics {
+        Diagnostics::new(|_| {})
+    }
+
+    /// print every message on its own line
+    pub fn stdout() -> Diagnostics {
+        Diagnostics::new(|msg| println!("{}", msg))
+    }
+
+    pub fn emit(&self, msg: &str) {
+        (self.0)(msg)
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Diagnostics::silent()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ANSTableUniform, AnsError, Diagnostics, ExplicitEncodeTable, Flipped,
+        GenericANSTableUniform, GenericPackedANSTable, GenericRANSTable, GenericSymbolFrequencies,
+        RangeAscending, RangeDescending, SpreadMethod, SpreadStrategy, StreamingANSUniform,
+        SymbolFrequencies, UniformTable,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn errors_instead_of_panics() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[b'a' as usize] = 1;
+        freqs.frequencies[b'b' as usize] = 3;
+
+        let traces = Arc::new(Mutex::new(Vec::new()));
+        let mut table = ANSTableUniform::new(freqs.clone());
+        let log = traces.clone();
+        table.diagnostics = Diagnostics::new(move |msg| log.lock().unwrap().push(msg.to_string()));
+        let mut coder = StreamingANSUniform::try_from_table(table, 16, 2).unwrap();
+        coder.verbose = true;
+        let encoded = coder.try_encode(b"ab".iter(), 1).unwrap();
+        assert!(!traces.lock().unwrap().is_empty());
+        assert_eq!(b"ba".to_vec(), coder.decode(&encoded, 1).unwrap());
+
+        assert_eq!(
+            Err(AnsError::ZeroInitialValue),
+            coder.try_encode(b"ab".iter(), 0)
+        );
+        assert_eq!(
+            Err(AnsError::SymbolNotInTable {
+                symbol: b'c' as usize
+            }),
+            coder.try_encode(b"abc".iter(), 1)
+        );
+        assert_eq!(Err(AnsError::ZeroInitialValue), coder.decode(&[], 0));
+        assert!(matches!(
+            StreamingANSUniform::try_new(freqs, 4, 1),
+            Err(AnsError::Unbalanced(_))
+        ));
+
+        assert!(matches!(
+            ANSTableUniform::with_strategy(
+                SymbolFrequencies::new(),
+                &SpreadMethod::Precise { phase: 0 }
+            ),
+            Err(AnsError::InvalidTable(_))
+        ));
+        assert!(matches!(
+            StreamingANSUniform::try_new(SymbolFrequencies::new(), 16, 2),
+            Err(AnsError::InvalidTable(_))
+        ));
+
+        // an alphabet wider than its symbol type
+        let mut wide = GenericSymbolFrequencies::<300>::new();
+        wide.frequencies[299] = 1;
+        wide.frequencies[0] = 1;
+        assert!(matches!(
+            GenericANSTableUniform::<u8, 300>::with_strategy(wide.clone(), &SpreadMethod::Step),
+            Err(AnsError::InvalidTable(_))
+        ));
+        assert!(matches!(
+            GenericRANSTable::<u8, 300>::new(wide),
+            Err(AnsError::InvalidTable(_))
+        ));
+
+        // frequencies whose sum does not fit a u32
+        let huge = [u32::MAX, 1];
+        assert!(matches!(
+            RangeDescending.encode_table(&huge),
+            Err(AnsError::InvalidTable(_))
+        ));
+        assert!(matches!(
+            Flipped(RangeAscending).encode_table(&huge),
+            Err(AnsError::InvalidTable(_))
+        ));
+        assert!(matches!(
+            Flipped(ExplicitEncodeTable(vec![vec![2], vec![0]])).encode_table(&[1, 1]),
+            Err(AnsError::InvalidTable(_))
+        ));
+
+        // a symbol past the end of the alphabet
+        let mut small = GenericSymbolFrequencies::<2>::new();
+        small.frequencies = [1, 3];
+        let table = GenericANSTableUniform::<u16, 2>::new(small);
+        let not_in_table = Err(AnsError::SymbolNotInTable { symbol: 5 });
+        assert_eq!(not_in_table, table.try_append_encode64(16, 5));
+        let packed = GenericPackedANSTable::from_table(&table);
+        assert_eq!(
+            not_in_table,
+            UniformTable::try_append_encode64(&packed, 16, 5)
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! A byte coder that can code any input with a table trained on something else.
+//!
+//! The table gets one more symbol, `ESCAPE`.  A byte the table has never seen is coded as `ESCAPE`
+//! followed by the byte itself with a flat 1/256 probability, so only the unseen bytes pay for it
+//! instead of spreading probability over all 256 symbols like `missing_symbols_become_one`.
+
+use crate::streaming::{DecodeSteps, EncodeFailure, StreamLoop};
+use crate::{
+    ANSTableUniform, AnsError, Diagnostics, GenericANSTableUniform, GenericStreamingANSUniform,
+    GenericSymbolFrequencies, NormalizationStrategy, StateWord, StreamingANSUniform,
+    SymbolFrequencies,
+};
+
+/// the extra symbol of the escaped table
+pub const ESCAPE: u16 = 256;
+
+pub struct StreamingANSEscaped {
+    /// the bytes plus `ESCAPE`
+    table: GenericANSTableUniform<u16, 257>,
+    /// every byte with a frequency of 1
+    literals: ANSTableUniform,
+    pub underflow_bits: u8,
+    pub bytes_to_stream: u8,
+    pub verbose: bool,
+    pub diagnostics: Diagnostics,
+}
+
+impl StreamingANSEscaped {
+    /// `freqs` is normalized to `1<<precision_bits` together with an `ESCAPE` that counts as
+    /// one occurrence, which keeps its cost to the bytes the table does know to a minimum.
+    pub fn new(
+        freqs: &SymbolFrequencies,
+        precision_bits: u8,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<StreamingANSEscaped, AnsError> {
+        let mut escaped = GenericSymbolFrequencies::<257>::new();
+        escaped.frequencies[..256].copy_from_slice(&freqs.frequencies);
+        escaped.frequencies[ESCAPE as usize] = 1;
+        let normalized = escaped
+            .normalize(precision_bits, NormalizationStrategy::Greedy)
+            .map_err(AnsError::InvalidTable)?;
+
+        let diagnostics = Diagnostics::default();
+        let mut table = GenericANSTableUniform::new(normalized.frequencies);
+        table.diagnostics = diagnostics.clone();
+        GenericStreamingANSUniform::<u16, 257>::check_balance(
+            &table,
+            underflow_bits,
+            bytes_to_stream,
+        )?;
+
+        let mut literals = ANSTableUniform::new(SymbolFrequencies {
+            frequencies: [1; 256],
+        });
+        literals.diagnostics = diagnostics.clone();
+        StreamingANSUniform::check_balance(&literals, underflow_bits, bytes_to_stream)?;
+
+        Ok(StreamingANSEscaped {
+            table,
+            literals,
+            underflow_bits,
+            bytes_to_stream,
+            verbose: false,
+            diagnostics,
+        })
+    }
+
+    /// the normalized frequency of `byte`; 0 means it will be escaped
+    pub fn frequency(&self, byte: u8) -> u32 {
+        self.table.frequencies[byte as usize]
+    }
+
+    fn stream_loop(&self) -> StreamLoop<'_> {
+        StreamLoop {
+            underflow_bits: self.underflow_bits,
+            quantum_bits: 8 * self.bytes_to_stream,
+            trace: Some(&self.diagnostics).filter(|_| self.verbose),
+        }
+    }
+
+    /// Unlike `StreamingANSUniform::encode`, `message` is in its natural order.
+    ///
+    /// For `initial_value` you probably want `1`, and you absolutely do not want `0`.
+    pub fn encode(&self, message: &[u8], initial_value: u64) -> Result<Vec<u8>, AnsError> {
+        // The start only has to be above the sum of the escaped table, even when a literal is coded
+        // first: the table holds 257 symbols, so its sum is at least 512 and above the 256 of the
+        // literals, and a literal's frequency of 1 moves every state anyway.
+        let initial_state = self
+            .stream_loop()
+            .initial_state(initial_value, self.table.sum_frequencies)?;
+        // backwards, so the decoder meets the ESCAPE before its literal
+        let steps = message.iter().rev().flat_map(|&byte| {
+            let (first, second) = if self.frequency(byte) > 0 {
+                (Step::Byte(byte), None)
+            } else {
+                (Step::Literal(byte), Some(Step::Escape))
+            };
+            std::iter::once(first).chain(second)
+        });
+
+        let mut rval = Vec::new();
+        self.stream_loop()
+            .encode(
+                steps,
+                |x: u64, step| match *step {
+                    Step::Byte(byte) => x.append_encode(&self.table, byte as u16),
+                    Step::Escape => x.append_encode(&self.table, ESCAPE),
+                    Step::Literal(byte) => x.append_encode(&self.literals, byte),
+                },
+                initial_state,
+                &mut |byte| {
+                    rval.push(byte);
+                    Ok(())
+                },
+            )
+            .map_err(|failure| match failure {
+                EncodeFailure::Sink(e) | EncodeFailure::Coder(e) => e,
+            })?;
+        Ok(rval)
+    }
+
+    /// `eos_marker` is the same value passed to `encode()` as `initial_value`
+    pub fn decode(&self, stream: &[u8], eos_marker: u64) -> Result<Vec<u8>, AnsError> {
+        let eos_state = self
+            .stream_loop()
+            .initial_state::<u64>(eos_marker, self.table.sum_frequencies)?;
+        let mut steps = EscapeSteps {
+            coder: self,
+            escaped: false,
+            out: Vec::new(),
+        };
+        self.stream_loop().decode(stream, eos_state, &mut steps)?;
+        Ok(steps.out)
+    }
+}
+
+/// what the escaped stream holds for one step of the coder
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    /// a byte the table knows
+    Byte(u8),
+    Escape,
+    /// the byte after an `ESCAPE`, coded with the flat table
+    Literal(u8),
+}
+
+/// `DecodeSteps` that switch to the literal table after an `ESCAPE`
+struct EscapeSteps<'a> {
+    coder: &'a StreamingANSEscaped,
+    /// the last symbol was an `ESCAPE`, so the next one is a literal
+    escaped: bool,
+    out: Vec<u8>,
+}
+
+impl DecodeSteps<u64> for EscapeSteps<'_> {
+    type Symbol = Step;
+
+    fn decode(&mut self, x: u64) -> Result<(Step, u64), AnsError> {
+        let (step, new_x) = if self.escaped {
+            let (byte, new_x) = x.decode_step(&self.coder.literals);
+            (Step::Literal(byte), new_x)
+        } else {
+            match x.decode_step(&self.coder.table) {
+                (ESCAPE, new_x) => (Step::Escape, new_x),
+                (symbol, new_x) => (Step::Byte(symbol as u8), new_x),
+            }
+        };
+        self.escaped = matches!(step, Step::Escape);
+        if let Step::Byte(byte) | Step::Literal(byte) = step {
+            self.out.push(byte);
+        }
+        Ok((step, new_x))
+    }
+
+    fn at_boundary(&self) -> bool {
+        !self.escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{StreamingANSEscaped, SymbolFrequencies};
+
+    #[test]
+    fn unseen_bytes_are_escaped() {
+        let training = b"the quick brown fox jumps over the lazy dog";
+        let mut freqs = SymbolFrequencies::new();
+        freqs.scan_file(&mut &training[..]).unwrap();
+
+        let coder = StreamingANSEscaped::new(&freqs, 12, 16, 2).unwrap();
+        assert_eq!(0, coder.frequency(b'!'));
+        assert!(coder.frequency(b' ') > 0);
+
+        let message: Vec<u8> = b"THE QUICK brown fox\0\xff jumps over the lazy dog!"
+            .iter()
+            .cycle()
+            .take(3000)
+            .cloned()
+            .collect();
+        let encoded = coder.encode(&message, 1).unwrap();
+        assert_eq!(message, coder.decode(&encoded, 1).unwrap());
+
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let encoded = coder.encode(&all_bytes, 1).unwrap();
+        assert_eq!(all_bytes, coder.decode(&encoded, 1).unwrap());
+        assert_eq!(
+            Vec::<u8>::new(),
+            coder.decode(&coder.encode(&[], 1).unwrap(), 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn unseen_first_byte() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.scan_file(&mut &b"abracadabra"[..]).unwrap();
+        for &(precision_bits, underflow_bits, bytes) in &[(10, 16, 2), (16, 16, 2), (16, 24, 1)] {
+            let coder =
+                StreamingANSEscaped::new(&freqs, precision_bits, underflow_bits, bytes).unwrap();
+            for message in &[&b"z"[..], b"za", b"zz", b"\0abra", b"!", b"a!z"] {
+                for &iv in &[1, 2, 1000] {
+                    let encoded = coder.encode(message, iv).unwrap();
+                    assert_eq!(
+                        message.to_vec(),
+                        coder.decode(&encoded, iv).unwrap(),
+                        "{} {:?} {}",
+                        precision_bits,
+                        message,
+                        iv
+                    );
+                }
+            }
+        }
+    }
+}
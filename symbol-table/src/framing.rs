@@ -0,0 +1,311 @@
+//! `io::Write` and `io::Read` adapters that run a streaming coder over input of any length.
+//!
+//! The coder has to see a message backwards, so the writer cuts its input into blocks and codes
+//! each one on its own.  Each block is framed as (all integers big-endian):
+//!
+//! | bytes | field                                   |
+//! |-------|-----------------------------------------|
+//! | 4     | decoded length, at most the block size  |
+//! | 4     | encoded length                          |
+//! | n     | the block as coded by `encode(.., 1)`   |
+//!
+//! A frame with both lengths 0 ends the stream, so a truncated stream is an error instead of a short read.
+//! Neither side holds more than one block of each.
+
+use crate::{ANSTableUniform, GenericStreamingANSUniform, UniformTable};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Error, ErrorKind, Read, Write};
+
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+/// Compresses everything written to it into `sink`.  Call `finish` to write the last block and the end marker;
+/// dropping the writer does it too, but ignores errors.
+pub struct AnsWriter<W: Write, T: UniformTable<u8> = ANSTableUniform> {
+    sink: Option<W>,
+    coder: GenericStreamingANSUniform<u8, 256, T>,
+    block_size: usize,
+    block: Vec<u8>,
+}
+
+impl<W: Write, T: UniformTable<u8>> AnsWriter<W, T> {
+    /// `block_size` must fit in a u32, or this fails with `InvalidInput`.  Bigger blocks amortize the
+    /// few bytes of framing and of flushing the coder's state, at the cost of memory on both ends.
+    pub fn new(
+        sink: W,
+        coder: GenericStreamingANSUniform<u8, 256, T>,
+        block_size: usize,
+    ) -> Result<AnsWriter<W, T>, Error> {
+        if block_size == 0 || block_size > u32::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("block size {} does not fit the frame header", block_size),
+            ));
+        }
+        Ok(AnsWriter {
+            sink: Some(sink),
+            coder,
+            block_size,
+            block: Vec::with_capacity(block_size),
+        })
+    }
+
+    fn write_block(&mut self) -> Result<(), Error> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let encoded = self.coder.encode_slice(&self.block, 1)?;
+        let sink = self.sink.as_mut().expect("writer already finished");
+        sink.write_u32::<BigEndian>(self.block.len() as u32)?;
+        sink.write_u32::<BigEndian>(encoded.len() as u32)?;
+        sink.write_all(&encoded)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    fn try_finish(&mut self) -> Result<(), Error> {
+        self.write_block()?;
+        if let Some(sink) = self.sink.as_mut() {
+            sink.write_u32::<BigEndian>(0)?;
+            sink.write_u32::<BigEndian>(0)?;
+            sink.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the last block and the end marker, and return the sink.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.try_finish()?;
+        Ok(self.sink.take().expect("writer already finished"))
+    }
+}
+
+impl<W: Write, T: UniformTable<u8>> Write for AnsWriter<W, T> {
+    /// A full block is only written out by the next call, so an error never follows accepting `buf`.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.block.len() == self.block_size {
+            self.write_block()?;
+        }
+        let count = buf.len().min(self.block_size - self.block.len());
+        self.block.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    /// Ends the current block early, so everything written so far can be decoded.
+    fn flush(&mut self) -> Result<(), Error> {
+        self.write_block()?;
+        self.sink.as_mut().expect("writer already finished").flush()
+    }
+}
+
+impl<W: Write, T: UniformTable<u8>> Drop for AnsWriter<W, T> {
+    fn drop(&mut self) {
+        if self.sink.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Decompresses the output of `AnsWriter` one block at a time.
+pub struct AnsReader<R: Read, T: UniformTable<u8> = ANSTableUniform> {
+    src: R,
+    coder: GenericStreamingANSUniform<u8, 256, T>,
+    max_block_size: usize,
+    /// `coder.max_encoded_len(max_block_size, 1)`
+    max_encoded_len: usize,
+    encoded: Vec<u8>,
+    block: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read, T: UniformTable<u8>> AnsReader<R, T> {
+    /// Blocks that claim to be longer than `max_block_size` (decoded), or longer than any block of
+    /// that size can encode to, are rejected before anything is allocated for them.
+    pub fn new(
+        src: R,
+        coder: GenericStreamingANSUniform<u8, 256, T>,
+        max_block_size: usize,
+    ) -> AnsReader<R, T> {
+        let max_encoded_len = coder.max_encoded_len(max_block_size, 1);
+        AnsReader {
+            src,
+            coder,
+            max_block_size,
+            max_encoded_len,
+            encoded: Vec::new(),
+            block: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    fn read_block(&mut self) -> Result<(), Error> {
+        let decoded_len = self.src.read_u32::<BigEndian>()? as usize;
+        let encoded_len = self.src.read_u32::<BigEndian>()? as usize;
+        if decoded_len == 0 && encoded_len == 0 {
+            self.finished = true;
+            return Ok(());
+        }
+        if decoded_len == 0
+            || decoded_len > self.max_block_size
+            || encoded_len > self.max_encoded_len
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "frame of {} bytes encoded as {} does not fit a block size of {}",
+                    decoded_len, encoded_len, self.max_block_size
+                ),
+            ));
+        }
+
+        self.encoded.resize(encoded_len, 0);
+        self.src.read_exact(&mut self.encoded)?;
+        // a frame that decodes to more than it claims fails with OutputTooSmall
+        self.block.resize(decoded_len, 0);
+        self.position = 0;
+        match self.coder.decode_into(&self.encoded, &mut self.block, 1) {
+            Ok(count) if count == decoded_len => {}
+            Ok(count) => {
+                self.block.clear();
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "frame decoded to {} bytes instead of {}",
+                        count, decoded_len
+                    ),
+                ));
+            }
+            Err(e) => {
+                self.block.clear();
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read, T: UniformTable<u8>> Read for AnsReader<R, T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        while self.position == self.block.len() && !self.finished {
+            self.read_block()?;
+        }
+        let count = buf.len().min(self.block.len() - self.position);
+        buf[..count].copy_from_slice(&self.block[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AnsReader, AnsWriter, NormalizationStrategy, StreamingANSUniform, SymbolFrequencies,
+    };
+    use std::cell::Cell;
+    use std::io::{Error, ErrorKind, Read, Write};
+    use std::rc::Rc;
+
+    fn coder() -> StreamingANSUniform {
+        let mut raw = SymbolFrequencies::new();
+        raw.frequencies.iter_mut().for_each(|f| *f = 1);
+        raw.frequencies[b'a' as usize] = 500;
+        raw.frequencies[b'b' as usize] = 200;
+        let freqs = raw
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+        StreamingANSUniform::new(freqs, 24, 2)
+    }
+
+    #[test]
+    fn round_trip_in_blocks() {
+        let message: Vec<u8> = (0..10_000u32)
+            .map(|i| {
+                if i % 7 == 0 {
+                    i as u8
+                } else {
+                    b'a' + (i % 3 == 0) as u8
+                }
+            })
+            .collect();
+
+        let mut writer = AnsWriter::new(Vec::new(), coder(), 1000).unwrap();
+        for chunk in message.chunks(333) {
+            writer.write_all(chunk).unwrap();
+        }
+        let compressed = writer.finish().unwrap();
+        assert!(compressed.len() < message.len() / 2);
+
+        let mut reader = AnsReader::new(&compressed[..], coder(), 1000);
+        let mut decoded = Vec::new();
+        let mut buf = [0; 77];
+        loop {
+            let count = reader.read(&mut buf).unwrap();
+            if count == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..count]);
+        }
+        assert_eq!(message, decoded);
+
+        let mut truncated = AnsReader::new(&compressed[..compressed.len() - 8], coder(), 1000);
+        let err = truncated.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, err.kind());
+
+        let mut too_small = AnsReader::new(&compressed[..], coder(), 999);
+        let err = too_small.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+
+        // the first frame claims 999 of its 1000 bytes
+        let mut lying = compressed.clone();
+        lying[3] -= 1;
+        let mut reader = AnsReader::new(&lying[..], coder(), 1000);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+    }
+
+    /// Fails every write while `broken` is set.
+    struct Flaky {
+        written: Vec<u8>,
+        broken: Rc<Cell<bool>>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            if self.broken.get() {
+                return Err(Error::other("broken sink"));
+            }
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_writes_accept_nothing() {
+        let err = AnsWriter::new(Vec::new(), coder(), 0).err().unwrap();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+
+        let broken = Rc::new(Cell::new(false));
+        let sink = Flaky {
+            written: Vec::new(),
+            broken: broken.clone(),
+        };
+        let mut writer = AnsWriter::new(sink, coder(), 10).unwrap();
+        assert_eq!(10, writer.write(b"abababababab").unwrap());
+        broken.set(true);
+        assert!(writer.write(b"ba").is_err());
+        broken.set(false);
+        assert_eq!(2, writer.write(b"ba").unwrap());
+        let compressed = writer.finish().unwrap().written;
+
+        let mut decoded = Vec::new();
+        AnsReader::new(&compressed[..], coder(), 10)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(b"abababababba".to_vec(), decoded);
+    }
+}
@@ -0,0 +1,252 @@
+//! Streaming ANS with `K` independent states, so consecutive steps do not wait on each other's divides.
+//!
+//! Symbol `i` of the message goes to state `i % K`, and all the states share one byte stream
+//! (Giesen, "Interleaved entropy coders").  Unlike `StreamingANSUniform`, every state stays in
+//! `[L, L<<(8*bytes_to_stream))` with `L = 1<<underflow_bits`, which is what lets the decoder tell
+//! whose quantum comes next without looking at the other states.  That needs `sum_frequencies` to be a
+//! power of two no bigger than `L`, like any normalized table.
+//!
+//! Each state starts at `L`.  After the message, the encoder flushes the states from `K-1` down to 0,
+//! so the decoder finds state 0 at the end of the stream and reads them back in order.
+
+use crate::{
+    AnsError, GenericANSTableUniform, GenericSymbolFrequencies, SpreadMethod, Symbol, UniformTable,
+};
+use std::marker::PhantomData;
+
+pub struct GenericInterleavedANS<
+    S: Symbol,
+    const N: usize,
+    const K: usize,
+    T: UniformTable<S> = GenericANSTableUniform<S, N>,
+> {
+    table: T,
+    /// a symbol's state is streamed before encoding it if it is at least `renormalize[symbol]`
+    renormalize: Vec<u64>,
+    underflow_bits: u8,
+    bytes_to_stream: u8,
+    symbol: PhantomData<S>,
+}
+
+/// bytes, with `K` states
+pub type InterleavedANS<const K: usize> = GenericInterleavedANS<u8, 256, K>;
+
+impl<S: Symbol, const N: usize, const K: usize> GenericInterleavedANS<S, N, K> {
+    /// `freqs` must sum to a power of two of at most `1<<underflow_bits`
+    pub fn new(
+        freqs: GenericSymbolFrequencies<N>,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<GenericInterleavedANS<S, N, K>, AnsError> {
+        Self::from_table(
+            GenericANSTableUniform::with_strategy(freqs, &SpreadMethod::Precise { phase: 0 })?,
+            underflow_bits,
+            bytes_to_stream,
+        )
+    }
+}
+
+impl<S: Symbol, const N: usize, const K: usize, T: UniformTable<S>>
+    GenericInterleavedANS<S, N, K, T>
+{
+    pub fn from_table(
+        table: T,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<GenericInterleavedANS<S, N, K, T>, AnsError> {
+        if K == 0 {
+            return Err(AnsError::Unbalanced("at least one state is needed".into()));
+        }
+        if bytes_to_stream == 0 || underflow_bits as u32 + 8 * bytes_to_stream as u32 > 63 {
+            return Err(AnsError::Unbalanced(format!(
+                "a state of {} + 8*{} bits does not fit in 63",
+                underflow_bits, bytes_to_stream
+            )));
+        }
+        let sum_frequencies = table.sum_frequencies();
+        if !sum_frequencies.is_power_of_two() || sum_frequencies as u64 > 1 << underflow_bits {
+            return Err(AnsError::InvalidTable(format!(
+                "interleaved coding needs frequencies that sum to a power of two up to 2^{}, not {}",
+                underflow_bits, sum_frequencies
+            )));
+        }
+
+        // (L / sum_frequencies) << (8*bytes_to_stream), times the frequency
+        let scale = ((1u64 << underflow_bits) / sum_frequencies as u64) << (8 * bytes_to_stream);
+        let renormalize = (0..table.alphabet_size())
+            .map(|symbol| scale * table.frequency(symbol) as u64)
+            .collect();
+
+        Ok(GenericInterleavedANS {
+            table,
+            renormalize,
+            underflow_bits,
+            bytes_to_stream,
+            symbol: PhantomData,
+        })
+    }
+
+    pub fn table(&self) -> &T {
+        &self.table
+    }
+
+    fn lower_bound(&self) -> u64 {
+        1 << self.underflow_bits
+    }
+
+    /// `message` is in its natural order
+    pub fn encode(&self, message: &[S]) -> Result<Vec<u8>, AnsError> {
+        let mut states = [self.lower_bound(); K];
+        let mut rval = Vec::new();
+
+        for (i, &symbol) in message.iter().enumerate().rev() {
+            let x = &mut states[i % K];
+            let limit = self.renormalize[symbol.to_index()];
+            if limit == 0 {
+                return Err(AnsError::SymbolNotInTable {
+                    symbol: symbol.to_index(),
+                });
+            }
+            // a rare symbol can need several quanta out before it fits
+            while *x >= limit {
+                *x = self.push_quantum(*x, &mut rval);
+            }
+            *x = self.table.try_append_encode64(*x, symbol)?;
+        }
+
+        for &state in states.iter().rev() {
+            let mut x = state;
+            while x != 0 {
+                x = self.push_quantum(x, &mut rval);
+            }
+        }
+        Ok(rval)
+    }
+
+    fn push_quantum(&self, mut x: u64, sink: &mut Vec<u8>) -> u64 {
+        for _i in 0..self.bytes_to_stream {
+            sink.push(x as u8);
+            x >>= 8;
+        }
+        x
+    }
+
+    pub fn decode(&self, stream: &[u8]) -> Result<Vec<S>, AnsError> {
+        let lower_bound = self.lower_bound();
+        let mut iter = stream.iter().rev().peekable();
+
+        let mut states = [0u64; K];
+        for x in states.iter_mut() {
+            while *x < lower_bound {
+                *x = self
+                    .read_quantum(&mut iter, *x)
+                    .ok_or(AnsError::EosNotReached)?;
+            }
+        }
+
+        let mut rval = Vec::new();
+        let mut i = 0;
+        loop {
+            if iter.peek().is_none() && states.iter().all(|&x| x == lower_bound) {
+                break;
+            }
+            let x = &mut states[i % K];
+            let (symbol, new_x) = self.table.decode64(*x);
+            *x = new_x;
+            while *x < lower_bound {
+                *x = self
+                    .read_quantum(&mut iter, *x)
+                    .ok_or(AnsError::EosNotReached)?;
+            }
+            rval.push(symbol);
+            i += 1;
+        }
+        Ok(rval)
+    }
+
+    /// `None` if the stream ends before a whole quantum
+    fn read_quantum(&self, iter: &mut dyn Iterator<Item = &u8>, mut x: u64) -> Option<u64> {
+        for _i in 0..self.bytes_to_stream {
+            x = (x << 8) | *iter.next()? as u64;
+        }
+        Some(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AnsError, GenericInterleavedANS, InterleavedANS, NormalizationStrategy, PackedANSTable,
+        SymbolFrequencies, UniformTable,
+    };
+
+    fn round_trip<const K: usize, T: UniformTable<u8>>(
+        coder: &GenericInterleavedANS<u8, 256, K, T>,
+        message: &[u8],
+    ) {
+        let encoded = coder.encode(message).unwrap();
+        assert_eq!(message, &coder.decode(&encoded).unwrap()[..], "K={}", K);
+    }
+
+    #[test]
+    fn round_trips() {
+        let sample =
+            b"she sells sea shells by the sea shore; the shells she sells are surely seashells";
+        let mut raw = SymbolFrequencies::new();
+        raw.scan_file(&mut &sample[..]).unwrap();
+        let freqs = raw
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+
+        for len in (0..20).chain(vec![999, 1000, 1001, 5000]) {
+            let message: Vec<u8> = sample.iter().cycle().take(len).cloned().collect();
+            for &(underflow_bits, bytes) in &[(16, 1), (16, 2), (24, 2), (31, 4)] {
+                round_trip(
+                    &InterleavedANS::<1>::new(freqs.clone(), underflow_bits, bytes).unwrap(),
+                    &message,
+                );
+                round_trip(
+                    &InterleavedANS::<2>::new(freqs.clone(), underflow_bits, bytes).unwrap(),
+                    &message,
+                );
+                round_trip(
+                    &InterleavedANS::<4>::new(freqs.clone(), underflow_bits, bytes).unwrap(),
+                    &message,
+                );
+                let packed = PackedANSTable::new(freqs.clone());
+                round_trip(
+                    &GenericInterleavedANS::<u8, 256, 8, _>::from_table(
+                        packed,
+                        underflow_bits,
+                        bytes,
+                    )
+                    .unwrap(),
+                    &message,
+                );
+            }
+        }
+
+        let coder = InterleavedANS::<4>::new(freqs.clone(), 16, 2).unwrap();
+        let encoded = coder.encode(&sample[..]).unwrap();
+        assert_eq!(Err(AnsError::EosNotReached), coder.decode(&encoded[1..]));
+        assert_eq!(
+            Err(AnsError::SymbolNotInTable {
+                symbol: b'!' as usize
+            }),
+            coder.encode(b"sells!")
+        );
+        assert!(InterleavedANS::<4>::new(freqs, 11, 2).is_err());
+
+        // a frequency of 1 in 4096 takes two 1-byte quanta out of a 16-bit state
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[0] = 4095;
+        freqs.frequencies[1] = 1;
+        let coder = InterleavedANS::<2>::new(freqs, 16, 1).unwrap();
+        for len in 0..40 {
+            let message: Vec<u8> = (0..len).map(|i| (i % 7 == 3) as u8).collect();
+            round_trip(&coder, &message);
+        }
+        round_trip(&coder, &[1; 100]);
+    }
+}
@@ -2,20 +2,148 @@ extern crate byteorder;
 
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
-use std::fmt::{Display, LowerHex};
-use std::io::{Error, Read};
+use std::convert::TryFrom;
+use std::fmt::{Debug, Display, LowerHex};
+use std::io::{Error, ErrorKind, Read};
+use std::marker::PhantomData;
+
+mod algebra;
+mod bit_io;
+mod compact_table;
+mod context;
+mod counting;
+mod error;
+mod escape;
+mod framing;
+mod interleaved;
+mod normalize;
+mod packed;
+mod parallel;
+mod rans;
+mod reciprocal;
+mod spread;
+mod statistics;
+mod streaming;
+mod table_file;
+mod tans;
+mod text_formats;
+
+pub use context::{ContextFrequencies, StreamingANSContext, INITIAL_CONTEXT};
+pub use counting::{DownscaledFrequencies, GenericSymbolCounts, SymbolCounts};
+pub use error::{AnsError, Diagnostics};
+pub use escape::{StreamingANSEscaped, ESCAPE};
+pub use framing::{AnsReader, AnsWriter, DEFAULT_BLOCK_SIZE};
+pub use interleaved::{GenericInterleavedANS, InterleavedANS};
+pub use normalize::{
+    normalization_cost, NormalizationStrategy, NormalizedFrequencies, MAX_NORMALIZATION_BITS,
+};
+pub use packed::{GenericPackedANSTable, PackedANSTable, StreamingANSPacked, UniformTable};
+pub use rans::{GenericRANSTable, GenericStreamingRANS, RANSTable, StreamingRANS};
+pub use reciprocal::Reciprocal;
+pub use spread::{
+    ExplicitEncodeTable, Flipped, RangeAscending, RangeDescending, SpreadMethod, SpreadStrategy,
+};
+pub use streaming::{check_stream_balance, StateWord};
+use streaming::{DecodeSteps, EncodeFailure, StreamLoop};
+pub use table_file::{TableHeader, TABLE_MAGIC, TABLE_VERSION};
+pub use tans::{GenericTabledANS, TabledANS, MAX_TANS_TABLE_BITS};
+pub use text_formats::TableFormat;
+
+/// A symbol from an alphabet of `N` symbols numbered `0..N`.
+/// The symbol type must be wide enough to hold `N-1`.
+pub trait Symbol: Copy + Debug {
+    fn to_index(self) -> usize;
+
+    /// `None` if `index` does not fit the symbol type
+    fn try_from_index(index: usize) -> Option<Self>;
+
+    /// Panics if `index` does not fit the symbol type; see `try_from_index`
+    fn from_index(index: usize) -> Self {
+        Self::try_from_index(index).unwrap_or_else(|| {
+            panic!(
+                "symbol {} does not fit in {}",
+                index,
+                std::any::type_name::<Self>()
+            )
+        })
+    }
+}
+
+macro_rules! impl_symbol {
+    ($($t:ty),*) => {
+        $(
+            impl Symbol for $t {
+                fn to_index(self) -> usize {
+                    self as usize
+                }
+
+                fn try_from_index(index: usize) -> Option<Self> {
+                    <$t>::try_from(index).ok()
+                }
+            }
+        )*
+    };
+}
 
-pub struct SymbolFrequencies {
-    pub frequencies: [u32; 256],
+/// The table builders' `from_index`: a symbol the table's symbol type can not hold makes the table invalid.
+fn table_symbol<S: Symbol>(index: usize) -> Result<S, AnsError> {
+    S::try_from_index(index).ok_or_else(|| {
+        AnsError::InvalidTable(format!(
+            "symbol {} does not fit in {}",
+            index,
+            std::any::type_name::<S>()
+        ))
+    })
 }
 
-impl SymbolFrequencies {
-    pub fn new() -> SymbolFrequencies {
-        SymbolFrequencies {
-            frequencies: [0; 256],
+impl_symbol!(u8, u16, u32, usize);
+
+/// Frequencies for an alphabet of `N` symbols.  `SymbolFrequencies` is the byte alphabet.
+#[derive(Clone)]
+pub struct GenericSymbolFrequencies<const N: usize> {
+    pub frequencies: [u32; N],
+}
+
+pub type SymbolFrequencies = GenericSymbolFrequencies<256>;
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    pub fn new() -> GenericSymbolFrequencies<N> {
+        GenericSymbolFrequencies {
+            frequencies: [0; N],
+        }
+    }
+
+    /// count each symbol of `symbols`
+    pub fn add_symbols<S: Symbol, I: IntoIterator<Item = S>>(&mut self, symbols: I) {
+        for symbol in symbols {
+            self.frequencies[symbol.to_index()] += 1;
+        }
+    }
+
+    /// the legacy headerless format: `N` big-endian u32s
+    pub fn parse_binary_symbol_table(
+        src: &mut dyn Read,
+    ) -> Result<GenericSymbolFrequencies<N>, Error> {
+        let mut frequencies = [0; N];
+        src.read_u32_into::<BigEndian>(&mut frequencies)?;
+        Ok(GenericSymbolFrequencies { frequencies })
+    }
+
+    pub fn missing_symbols_become_one(
+        src: &GenericSymbolFrequencies<N>,
+    ) -> GenericSymbolFrequencies<N> {
+        let mut new_frequencies: [u32; N] = [0; N];
+        for (symbol, &freq) in src.frequencies.iter().enumerate() {
+            new_frequencies[symbol] = if freq > 0 { freq } else { 1 };
+        }
+        GenericSymbolFrequencies {
+            frequencies: new_frequencies,
         }
     }
+}
 
+impl SymbolFrequencies {
+    /// Fails instead of wrapping if a symbol occurs more than `u32::MAX` times; use `SymbolCounts` for inputs that big.
     pub fn scan_file(&mut self, f: &mut dyn Read) -> Result<(), Error> {
         let mut buffer = [0; 4 << 10];
 
@@ -25,30 +153,30 @@ impl SymbolFrequencies {
                 break;
             }
             for &symbol in &buffer[..count] {
-                self.frequencies[symbol as usize] += 1;
+                let freq = &mut self.frequencies[symbol as usize];
+                *freq = freq
+                    .checked_add(1)
+                    .ok_or_else(|| count_overflow(symbol, "count it with SymbolCounts"))?;
             }
         }
         Ok(())
     }
+}
 
-    pub fn parse_binary_symbol_table(src: &mut dyn Read) -> Result<SymbolFrequencies, Error> {
-        let mut frequencies = [0; 256];
-        src.read_u32_into::<BigEndian>(&mut frequencies)?;
-        Ok(SymbolFrequencies { frequencies })
-    }
-
-    pub fn missing_symbols_become_one(src: &SymbolFrequencies) -> SymbolFrequencies {
-        let mut new_frequencies: [u32; 256] = [0; 256];
-        for (symbol, &freq) in src.frequencies.iter().enumerate() {
-            new_frequencies[symbol] = if freq > 0 { freq } else { 1 };
-        }
-        SymbolFrequencies {
-            frequencies: new_frequencies,
-        }
-    }
+/// `advice` says what the caller can do instead
+pub(crate) fn count_overflow(symbol: u8, advice: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "symbol {} occurs more than {} times; {}",
+            symbol,
+            u32::MAX,
+            advice
+        ),
+    )
 }
 
-impl Default for SymbolFrequencies {
+impl<const N: usize> Default for GenericSymbolFrequencies<N> {
     fn default() -> Self {
         Self::new()
     }
@@ -58,52 +186,60 @@ impl Default for SymbolFrequencies {
 //
 //
 
+/// Uniform-spread ANS table for symbols of type `S` from an alphabet of `N`.  `ANSTableUniform` is the byte alphabet.
 #[derive(Clone)]
-pub struct ANSTableUniform {
-    pub frequencies: [u32; 256],
+pub struct GenericANSTableUniform<S: Symbol, const N: usize> {
+    pub frequencies: [u32; N],
     pub sum_frequencies: u32,
     pub encode: Vec<Vec<u32>>,
-    pub decode: Vec<(u8, u32)>,
+    pub decode: Vec<(S, u32)>,
     pub verbose: bool,
+    pub diagnostics: Diagnostics,
+    /// `frequencies` and `sum_frequencies` as multipliers; `None` for symbols that are not in the table
+    reciprocals: Vec<Option<Reciprocal>>,
+    sum_reciprocal: Reciprocal,
 }
 
-impl ANSTableUniform {
-    pub fn new(freqs: SymbolFrequencies) -> ANSTableUniform {
-        let frequencies = freqs.frequencies;
-        let sum_frequencies = frequencies.iter().sum();
-        //println!("sum_frequencies = {}", sum_frequencies);
+pub type ANSTableUniform = GenericANSTableUniform<u8, 256>;
 
-        let (transforms, backward) =
-            ANSTableUniform::build_tables(&frequencies, sum_frequencies, 0);
+/// the `encode` and `decode` fields of a `GenericANSTableUniform`
+pub type EncodeDecodeTables<S> = (Vec<Vec<u32>>, Vec<(S, u32)>);
 
-        ANSTableUniform {
-            frequencies,
-            sum_frequencies,
-            encode: transforms,
-            decode: backward,
-            verbose: false,
-        }
+impl<S: Symbol, const N: usize> GenericANSTableUniform<S, N> {
+    /// Spread with the accumulator method (see `build_tables`), computed in O(L log N) by `SpreadMethod::Precise`
+    pub fn new(freqs: GenericSymbolFrequencies<N>) -> GenericANSTableUniform<S, N> {
+        Self::with_spread(freqs, SpreadMethod::Precise { phase: 0 })
     }
 
+    /// Panics if `sum_frequencies` is not the sum of `frequencies`; see `try_build_tables`
     pub fn build_tables(
-        frequencies: &[u32; 256],
+        frequencies: &[u32; N],
         sum_frequencies: u32,
         accum_start: u32,
-    ) -> (Vec<Vec<u32>>, Vec<(u8, u32)>) {
-        let mut transforms: Vec<Vec<u32>> = (0..256).map(|_| Vec::new()).collect();
-        let mut backward: Vec<(u8, u32)> = Vec::new();
+    ) -> (Vec<Vec<u32>>, Vec<(S, u32)>) {
+        Self::try_build_tables(frequencies, sum_frequencies, accum_start)
+            .unwrap_or_else(|e| panic!("malfunction building symbol table: {}", e))
+    }
 
-        let mut accum = [accum_start; 256];
+    pub fn try_build_tables(
+        frequencies: &[u32; N],
+        sum_frequencies: u32,
+        accum_start: u32,
+    ) -> Result<EncodeDecodeTables<S>, AnsError> {
+        let mut transforms: Vec<Vec<u32>> = (0..N).map(|_| Vec::new()).collect();
+        let mut backward: Vec<(S, u32)> = Vec::new();
+
+        let mut accum = [accum_start; N];
 
         let mut cursor = 0;
 
         for _i in 0..sum_frequencies {
-            for symbol in 0..256 {
+            for symbol in 0..N {
                 accum[symbol] += frequencies[symbol];
                 if accum[symbol] >= sum_frequencies {
                     let decoded = transforms[symbol].len();
                     transforms[symbol].push(cursor);
-                    backward.push((symbol as u8, decoded as u32));
+                    backward.push((table_symbol(symbol)?, decoded as u32));
 
                     cursor += 1;
                     accum[symbol] -= sum_frequencies;
@@ -111,122 +247,113 @@ impl ANSTableUniform {
             }
         }
 
-        assert!(
-            cursor == sum_frequencies,
-            "malfunction building symbol table: (cursor ={}) != (sum_frequencies={})",
-            cursor,
-            sum_frequencies
-        );
+        if cursor != sum_frequencies {
+            return Err(AnsError::InvalidTable(format!(
+                "(cursor ={}) != (sum_frequencies={})",
+                cursor, sum_frequencies
+            )));
+        }
 
-        for (i, &a) in accum.iter().enumerate() {
-            if a != accum_start {
-                println!("unexpected accum[{}] == {}", i, a);
-            }
+        if let Some((i, a)) = accum.iter().enumerate().find(|(_, &a)| a != accum_start) {
+            return Err(AnsError::InvalidTable(format!(
+                "unexpected accum[{}] == {}",
+                i, a
+            )));
         }
-        (transforms, backward)
+        Ok((transforms, backward))
     }
 
-    pub fn append_encode(&self, val: u32, symbol: u8) -> u32 {
+    pub fn append_encode(&self, val: u32, symbol: S) -> u32 {
         self.append_encode32(val, symbol)
     }
 
-    pub fn append_encode32(&self, val: u32, symbol: u8) -> u32 {
-        let freq = self.frequencies[symbol as usize];
-        let cycle = val / freq;
-        let phase = val % freq;
-        let encoded = self.encode[symbol as usize][phase as usize];
+    pub fn append_encode32(&self, val: u32, symbol: S) -> u32 {
+        let freq = self.frequencies[symbol.to_index()];
+        let (cycle, phase) = self
+            .frequency_reciprocal(symbol)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .div_rem(val as u64);
+        let (cycle, phase) = (cycle as u32, phase as u32);
+        let encoded = self.encode[symbol.to_index()][phase as usize];
         let rval = cycle * self.sum_frequencies + encoded;
         if self.verbose {
-            ANSTableUniform::log_encode(
-                val,
-                freq,
-                cycle,
-                phase,
-                encoded,
-                rval,
-                self.sum_frequencies,
-            );
+            self.log_encode(val, freq, cycle, phase, encoded, rval);
         }
         rval
     }
 
+    /// also fails for a symbol past the end of the alphabet
+    fn frequency_reciprocal(&self, symbol: S) -> Result<&Reciprocal, AnsError> {
+        self.reciprocals
+            .get(symbol.to_index())
+            .and_then(Option::as_ref)
+            .ok_or(AnsError::SymbolNotInTable {
+                symbol: symbol.to_index(),
+            })
+    }
+
     fn log_encode<T: Display + LowerHex>(
+        &self,
         val: T,
         symbol_frequency: u32,
         cycle: T,
         phase: T,
         encoded: u32,
         rval: T,
-        sum_frequencies: u32,
     ) {
-        println!(
+        self.diagnostics.emit(&format!(
             "{} = {}*{} + {} ;  rval = {}*{} + {} = 0x{:x}",
-            val, symbol_frequency, cycle, phase, sum_frequencies, cycle, encoded, rval
-        );
+            val, symbol_frequency, cycle, phase, self.sum_frequencies, cycle, encoded, rval
+        ));
     }
 
-    pub fn append_encode64(&self, val: u64, symbol: u8) -> u64 {
-        let freq = self.frequencies[symbol as usize];
-        assert!(
-            freq != 0,
-            "symbol {} does not appear in symbol table",
-            symbol
-        );
-        let cycle = val / (freq as u64);
-        let phase = val % (freq as u64);
-        let encoded = self.encode[symbol as usize][phase as usize];
+    /// Panics if `symbol` is not in the table; see `try_append_encode64`
+    pub fn append_encode64(&self, val: u64, symbol: S) -> u64 {
+        self.try_append_encode64(val, symbol)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_append_encode64(&self, val: u64, symbol: S) -> Result<u64, AnsError> {
+        let (cycle, phase) = self.frequency_reciprocal(symbol)?.div_rem(val);
+        let freq = self.frequencies[symbol.to_index()];
+        let encoded = self.encode[symbol.to_index()][phase as usize];
         //println!("debug for {}@{} :\t {:x}*{}+{}", symbol, freq, cycle, self.sum_frequencies, encoded);
         let rval = cycle * (self.sum_frequencies as u64) + (encoded as u64);
         if self.verbose {
-            ANSTableUniform::log_encode(
-                val,
-                freq,
-                cycle,
-                phase,
-                encoded,
-                rval,
-                self.sum_frequencies,
-            );
+            self.log_encode(val, freq, cycle, phase, encoded, rval);
         }
-        rval
+        Ok(rval)
     }
 
-    pub fn decode32(&self, val: u32) -> (u8, u32) {
-        let cycle = val / self.sum_frequencies;
-        let phase = val % self.sum_frequencies;
+    pub fn decode32(&self, val: u32) -> (S, u32) {
+        let (cycle, phase) = self.sum_reciprocal.div_rem(val as u64);
+        let (cycle, phase) = (cycle as u32, phase as u32);
 
         let (symbol, tmp) = self.decode[phase as usize];
-        let sym_freq = self.frequencies[symbol as usize];
+        let sym_freq = self.frequencies[symbol.to_index()];
         let rval = cycle * sym_freq + tmp;
         if self.verbose {
-            ANSTableUniform::log_decode(
-                val,
-                self.sum_frequencies,
-                cycle,
-                phase,
-                tmp,
-                sym_freq,
-                rval,
-            );
+            self.log_decode(val, self.sum_frequencies, cycle, phase, tmp, sym_freq, rval);
         }
         (symbol, rval)
     }
 
-    pub fn decode64(&self, val: u64) -> (u8, u64) {
+    pub fn decode64(&self, val: u64) -> (S, u64) {
         let sum_frequencies = self.sum_frequencies as u64;
-        let cycle = val / sum_frequencies;
-        let phase = val % sum_frequencies;
+        let (cycle, phase) = self.sum_reciprocal.div_rem(val);
 
         let (symbol, tmp) = self.decode[phase as usize];
-        let sym_freq = self.frequencies[symbol as usize];
+        let sym_freq = self.frequencies[symbol.to_index()];
         let rval = cycle * (sym_freq as u64) + (tmp as u64);
         if self.verbose {
-            ANSTableUniform::log_decode(val, sum_frequencies, cycle, phase, tmp, sym_freq, rval);
+            self.log_decode(val, sum_frequencies, cycle, phase, tmp, sym_freq, rval);
         }
         (symbol, rval)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn log_decode<T: Display + LowerHex>(
+        &self,
         val: T,
         sum_frequencies: T,
         cycle: T,
@@ -235,10 +362,10 @@ impl ANSTableUniform {
         sym_freq: u32,
         rval: T,
     ) {
-        println!(
+        self.diagnostics.emit(&format!(
             "{} = {}*{} + {}; rval = {}*{} + {} = 0x{:x}",
             val, sum_frequencies, cycle, phase, rval, sym_freq, tmp, rval
-        );
+        ));
     }
 }
 
@@ -246,109 +373,147 @@ impl ANSTableUniform {
 //
 //
 
-pub struct StreamingANSUniform {
-    pub table: ANSTableUniform,
+/// Streaming coder for symbols of type `S` from an alphabet of `N`.  `StreamingANSUniform` is the byte alphabet.
+///
+/// Warnings and `verbose` traces go to `table.diagnostics`.
+///
+/// `T` is the table layout; see `PackedANSTable` for the compact one, and `GenericRANSTable` for rANS.
+/// The state is a `W`, and it streams `quantum_bits` at a time (see `streaming`).
+pub struct GenericStreamingANSUniform<
+    S: Symbol,
+    const N: usize,
+    T: UniformTable<S> = GenericANSTableUniform<S, N>,
+    W: StateWord = u64,
+> {
+    pub table: T,
     pub underflow_bits: u8,
-    pub bytes_to_stream: u8,
+    pub quantum_bits: u8,
     pub verbose: bool,
+    phantom: PhantomData<(S, W)>,
 }
 
-impl StreamingANSUniform {
+pub type StreamingANSUniform = GenericStreamingANSUniform<u8, 256>;
+
+impl<S: Symbol, const N: usize, W: StateWord>
+    GenericStreamingANSUniform<S, N, GenericANSTableUniform<S, N>, W>
+{
     /// A good value for `underflow_bits` is 16
     ///
     /// A good value for `bytes_to_stream` is `underflow_bits/8`
+    ///
+    /// Panics if the parameters do not fit the table; see `try_new`
     pub fn new(
-        freqs: SymbolFrequencies,
+        freqs: GenericSymbolFrequencies<N>,
         underflow_bits: u8,
         bytes_to_stream: u8,
-    ) -> StreamingANSUniform {
-        let table = ANSTableUniform::new(freqs);
-        StreamingANSUniform::panic_if_unbalanced(&table, underflow_bits, bytes_to_stream);
+    ) -> Self {
+        Self::try_new(freqs, underflow_bits, bytes_to_stream).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        StreamingANSUniform {
-            table,
+    pub fn try_new(
+        freqs: GenericSymbolFrequencies<N>,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<Self, AnsError> {
+        Self::try_from_table(
+            GenericANSTableUniform::with_strategy(freqs, &SpreadMethod::Precise { phase: 0 })?,
             underflow_bits,
             bytes_to_stream,
-            verbose: false,
-        }
+        )
     }
+}
 
-    pub fn panic_if_unbalanced(table: &ANSTableUniform, underflow_bits: u8, bytes_to_stream: u8) {
-        let bits_to_stream = 8 * bytes_to_stream;
-        assert!(
-            underflow_bits >= bits_to_stream,
-            "underflow_bits {} is too small ( < 8*{} )",
-            underflow_bits,
-            bytes_to_stream
-        );
+impl<S: Symbol, const N: usize, T: UniformTable<S>, W: StateWord>
+    GenericStreamingANSUniform<S, N, T, W>
+{
+    /// for tables built with something other than `GenericANSTableUniform::new`
+    pub fn from_table(table: T, underflow_bits: u8, bytes_to_stream: u8) -> Self {
+        Self::try_from_table(table, underflow_bits, bytes_to_stream)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
-        let max_result_bits = underflow_bits + 2 * bits_to_stream;
-        if 64 < max_result_bits {
-            panic!(
-                "encoding process will probably overflow ( 64 < {} + 2*{} )",
-                underflow_bits, bits_to_stream
-            );
-        }
+    /// Install the table's `diagnostics` first if you want to see the warnings from `check_balance`.
+    pub fn try_from_table(
+        table: T,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<Self, AnsError> {
+        Self::with_quantum_bits(table, underflow_bits, bytes_to_stream.saturating_mul(8))
+    }
 
-        let max_working_x = (1u64 << (underflow_bits + bits_to_stream)) - 1;
-        for (symbol, &frequency) in table.frequencies.iter().enumerate() {
-            if frequency == 0 {
-                continue;
-            }
-            if frequency > 0 && table.sum_frequencies > (frequency << (bits_to_stream)) {
-                println!("symbol {} frequency is small enough that encoding could jump by too many bits ( {} > {} << (8*{}) )",
-                         symbol, table.sum_frequencies, frequency, bytes_to_stream);
-            }
+    /// `try_from_table` for quanta that need not be whole bytes; see `check_stream_balance`
+    pub fn with_quantum_bits(
+        table: T,
+        underflow_bits: u8,
+        quantum_bits: u8,
+    ) -> Result<Self, AnsError> {
+        check_stream_balance::<W, S, T>(&table, underflow_bits, quantum_bits)?;
 
-            let cycle = max_working_x / (frequency as u64);
-            let jump = *table.encode[symbol].last().unwrap();
-            let x2 = (cycle as u128) * (table.sum_frequencies as u128) + (jump as u128);
-            if x2 >> (max_result_bits) > 0 {
-                panic!("symbol {} frequency is small enough that encoding could jump by too many bits {:x}.{} = {:x} >= (1<<{})",
-                       symbol, max_working_x, symbol, x2, max_result_bits);
-            }
-        }
+        Ok(GenericStreamingANSUniform {
+            table,
+            underflow_bits,
+            quantum_bits,
+            verbose: false,
+            phantom: PhantomData,
+        })
+    }
+
+    pub fn panic_if_unbalanced(table: &T, underflow_bits: u8, bytes_to_stream: u8) {
+        Self::check_balance(table, underflow_bits, bytes_to_stream)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 
-    /// For `message_backwards` you probably want something like `message.iter().rev()`.
+    /// Fails if encoding with these parameters could overflow the state.
+    /// Symbols rare enough to make the stream inefficient are only reported to `table.diagnostics`.
+    ///
+    /// This is `check_stream_balance` for whole-byte quanta.
+    pub fn check_balance(
+        table: &T,
+        underflow_bits: u8,
+        bytes_to_stream: u8,
+    ) -> Result<(), AnsError> {
+        check_stream_balance::<W, S, T>(table, underflow_bits, bytes_to_stream.saturating_mul(8))
+    }
+
+    /// For `message_backwards` you probably want something like `message.iter().rev()`, or
+    /// `encode_slice`, which takes the message in its natural order.
     ///
     /// For `initial_value` you probably want `1`, and you absolutely do not want `0`.
+    ///
+    /// Panics on a symbol that is not in the table; see `try_encode`
     pub fn encode<'a, I>(&self, message_backwards: I, initial_value: u64) -> Vec<u8>
     where
-        I: Iterator<Item = &'a u8>,
+        I: Iterator<Item = &'a S>,
+        S: 'a,
     {
-        if initial_value == 0 {
-            panic!("initial_value for encode() must not be {}", initial_value);
-        }
-        let mut x = initial_value;
+        self.try_encode(message_backwards, initial_value)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
 
+    pub fn try_encode<'a, I>(
+        &self,
+        message_backwards: I,
+        initial_value: u64,
+    ) -> Result<Vec<u8>, AnsError>
+    where
+        I: Iterator<Item = &'a S>,
+        S: 'a,
+    {
         let mut rval: Vec<u8> = Vec::new();
-        let mut rval_sink = |byte| rval.push(byte);
-        for &symbol in message_backwards {
-            //println!("symbol = {}", symbol);
-            let mut new_x = self.table.append_encode64(x, symbol);
-            if new_x >> (self.underflow_bits + 8 * self.bytes_to_stream) != 0 {
-                if self.verbose {
-                    println!("{:x}.{} overflows to {:x}", x, symbol as char, new_x)
-                }
-                x = self.push_quantum(x, &mut rval_sink);
-                new_x = self.table.append_encode64(x, symbol);
-            }
-            if self.verbose {
-                println!("{:x}.{} becomes {:x}", x, symbol as char, new_x);
-            }
-            x = new_x;
-        }
-        //println!("exhausted bytes to encode");
-
-        while x != 0 {
-            x = self.push_quantum(x, &mut rval_sink);
-        }
-
-        rval
+        self.try_encode_to_sink(
+            message_backwards,
+            &mut |byte| {
+                rval.push(byte);
+                Ok(())
+            },
+            initial_value,
+        )?;
+        Ok(rval)
     }
 
     /// for `message_backwards` you probably want something like `message.iter().rev()`
+    ///
+    /// Panics on a symbol that is not in the table; see `try_encode_to_sink`
     pub fn encode_to_sink<'a, I, E>(
         &self,
         message_backwards: I,
@@ -356,140 +521,243 @@ impl StreamingANSUniform {
         initial_value: u64,
     ) -> Result<(), E>
     where
-        I: Iterator<Item = &'a u8>,
+        I: Iterator<Item = &'a S>,
+        S: 'a,
     {
-        if initial_value == 0 {
-            panic!("initial_value for encode() must not be {}", initial_value);
-        }
-        let mut x = initial_value;
-
-        for &symbol in message_backwards {
-            //println!("symbol = {}", symbol);
-            let mut new_x = self.table.append_encode64(x, symbol);
-            if new_x >> (self.underflow_bits + 8 * self.bytes_to_stream) != 0 {
-                if self.verbose {
-                    println!("{:x}.{} overflows to {:x}", x, symbol as char, new_x)
-                }
-                x = self.push_quantum2(x, sink)?;
-                new_x = self.table.append_encode64(x, symbol);
-            }
-            if self.verbose {
-                println!("{:x}.{} becomes {:x}", x, symbol as char, new_x);
-            }
-            x = new_x;
+        match self.encode_core(message_backwards, sink, initial_value) {
+            Ok(()) => Ok(()),
+            Err(EncodeFailure::Sink(e)) => Err(e),
+            Err(EncodeFailure::Coder(e)) => panic!("{}", e),
         }
-        //println!("exhausted bytes to encode");
+    }
 
-        while x != 0 {
-            x = self.push_quantum2(x, sink)?;
-        }
+    /// Like `encode_to_sink`, but coder errors are reported through the sink's error type.
+    pub fn try_encode_to_sink<'a, I, E>(
+        &self,
+        message_backwards: I,
+        sink: &mut dyn FnMut(u8) -> Result<(), E>,
+        initial_value: u64,
+    ) -> Result<(), E>
+    where
+        I: Iterator<Item = &'a S>,
+        S: 'a,
+        E: From<AnsError>,
+    {
+        self.encode_core(message_backwards, sink, initial_value)
+            .map_err(|failure| match failure {
+                EncodeFailure::Sink(e) => e,
+                EncodeFailure::Coder(e) => e.into(),
+            })
+    }
 
-        Ok(())
+    /// Encode `message` in its natural order; `decode` returns it unchanged.
+    ///
+    /// For `initial_value` you probably want `1`, and you absolutely do not want `0`.
+    pub fn encode_slice(&self, message: &[S], initial_value: u64) -> Result<Vec<u8>, AnsError> {
+        self.try_encode(message.iter().rev(), initial_value)
     }
 
-    fn push_quantum(&self, mut x: u64, sink: &mut dyn FnMut(u8)) -> u64 {
-        for _i in 0..self.bytes_to_stream {
-            if self.verbose {
-                println!("push LSB of {:x} to stream", x);
-            }
-            sink(x as u8);
-            x >>= 8;
-        }
-        x
+    /// `encode_slice` for sources that can not be reversed; they are buffered first.
+    pub fn encode_iter<I>(&self, message: I, initial_value: u64) -> Result<Vec<u8>, AnsError>
+    where
+        I: IntoIterator<Item = S>,
+    {
+        let buffered: Vec<S> = message.into_iter().collect();
+        self.encode_slice(&buffered, initial_value)
     }
 
-    fn push_quantum2<E>(
+    /// `encode_slice` for a message that arrives in pieces, each in its natural order.
+    /// The pieces are kept as they are instead of being copied into one buffer.
+    pub fn encode_chunks<I, C>(&self, chunks: I, initial_value: u64) -> Result<Vec<u8>, AnsError>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[S]>,
+    {
+        let chunks: Vec<C> = chunks.into_iter().collect();
+        self.try_encode(
+            chunks
+                .iter()
+                .rev()
+                .flat_map(|chunk| chunk.as_ref().iter().rev()),
+            initial_value,
+        )
+    }
+
+    /// `try_encode_to_sink` for `message` in its natural order
+    pub fn encode_slice_to_sink<E>(
         &self,
-        mut x: u64,
+        message: &[S],
         sink: &mut dyn FnMut(u8) -> Result<(), E>,
-    ) -> Result<u64, E> {
-        for _i in 0..self.bytes_to_stream {
-            if self.verbose {
-                println!("push LSB of {:x} to stream", x);
-            }
-            sink(x as u8)?;
-            x >>= 8;
-        }
-        Ok(x)
+        initial_value: u64,
+    ) -> Result<(), E>
+    where
+        E: From<AnsError>,
+    {
+        self.try_encode_to_sink(message.iter().rev(), sink, initial_value)
     }
 
-    /// `eos_marker` is the same value passed to `encode()` as `initial_value`
-    pub fn decode(&self, stream: &[u8], eos_marker: u64) -> Result<Vec<u8>, String> {
-        //let mut cursor: i64 = (stream.len() - 1) as i64;
-        if eos_marker == 0 {
-            panic!("eos_marker for decode() must not be {}", eos_marker);
-        }
-
-        let mut iter = stream.iter().rev();
-
-        let mut rval = Vec::new();
-        let mut x: u64 = 0;
+    /// `encode_slice` into `out`, returning how many bytes of it were used.
+    /// A buffer of `max_encoded_len` bytes is always big enough.
+    pub fn encode_into(
+        &self,
+        message: &[S],
+        out: &mut [u8],
+        initial_value: u64,
+    ) -> Result<usize, AnsError> {
+        let capacity = out.len();
+        let mut used = 0;
+        self.encode_slice_to_sink(
+            message,
+            &mut |byte| {
+                *out.get_mut(used)
+                    .ok_or(AnsError::OutputTooSmall { capacity })? = byte;
+                used += 1;
+                Ok(())
+            },
+            initial_value,
+        )?;
+        Ok(used)
+    }
 
-        while x >> self.underflow_bits == 0 {
-            match self.read_quantum(&mut iter, x) {
-                None => break,
-                Some(new_x) => x = new_x,
-            }
+    /// The most bytes `encode` can produce for a message of `message_len` symbols.
+    ///
+    /// Every symbol adds at most `bits(sum_frequencies) - bits(frequency) + 2` bits to the state and the
+    /// streamed quanta together, and flushing the state at the end rounds up to a whole quantum.
+    pub fn max_encoded_len(&self, message_len: usize, initial_value: u64) -> usize {
+        fn bits(x: u64) -> u64 {
+            64 - x.leading_zeros() as u64
         }
-        loop {
-            //println!("decode state x={}", x);
-            if x >> self.underflow_bits == 0 {
-                match self.read_quantum(&mut iter, x) {
-                    None => break,
-                    Some(new_x) => x = new_x,
-                }
-            }
 
-            let (symbol, new_x) = self.table.decode64(x);
-            if self.verbose {
-                println!("{:x} becomes {:x}.'{}'", x, new_x, symbol as char);
-            }
-            rval.push(symbol);
-            x = new_x;
-        }
+        let sum = self.table.sum_frequencies() as u64;
+        let min_frequency = (0..self.table.alphabet_size())
+            .map(|symbol| self.table.frequency(symbol))
+            .filter(|&frequency| frequency > 0)
+            .min()
+            .unwrap_or(1) as u64;
+        // A state shifted down by a quantum is still at least the frequency of the symbol that
+        // overflowed it as long as `sum_frequencies <= 1<<(underflow_bits-1)`.  Above that the
+        // shifted state can be small enough to lose the `- bits(frequency)`.
+        let bits_per_symbol = if sum as u128 <= 1 << (self.underflow_bits.max(1) - 1) {
+            bits(sum) - bits(min_frequency) + 2
+        } else {
+            bits(sum) + 1
+        };
+
+        let initial_bits = bits(initial_value.saturating_add(sum.saturating_sub(1)));
+        let total_bits = (message_len as u64)
+            .saturating_mul(bits_per_symbol)
+            .saturating_add(initial_bits)
+            .saturating_add(self.quantum_bits as u64);
+        usize::try_from(total_bits.div_ceil(8)).unwrap_or(usize::MAX)
+    }
 
-        while x != eos_marker {
-            let (symbol, new_x) = self.table.decode64(x);
-            if self.verbose {
-                println!("{:x} becomes {:x}.'{}'", x, new_x, symbol as char);
-            }
-            rval.push(symbol);
-            if new_x < 1 {
-                return Err("failed to reach EOS marker".to_string());
-            }
-            x = new_x;
+    fn stream_loop(&self) -> StreamLoop<'_> {
+        StreamLoop {
+            underflow_bits: self.underflow_bits,
+            quantum_bits: self.quantum_bits,
+            trace: Some(self.table.diagnostics()).filter(|_| self.verbose),
         }
+    }
+
+    fn encode_core<'a, I, E>(
+        &self,
+        message_backwards: I,
+        sink: &mut dyn FnMut(u8) -> Result<(), E>,
+        initial_value: u64,
+    ) -> Result<(), EncodeFailure<E>>
+    where
+        I: Iterator<Item = &'a S>,
+        S: 'a,
+    {
+        let initial_state = self
+            .stream_loop()
+            .initial_state::<W>(initial_value, self.table.sum_frequencies())
+            .map_err(EncodeFailure::Coder)?;
+        self.stream_loop().encode(
+            message_backwards,
+            |x: W, &&symbol| x.append_encode(&self.table, symbol),
+            initial_state,
+            sink,
+        )
+    }
 
+    /// `eos_marker` is the same value passed to `encode()` as `initial_value`
+    pub fn decode(&self, stream: &[u8], eos_marker: u64) -> Result<Vec<S>, AnsError> {
+        let mut rval = Vec::new();
+        self.decode_core(
+            stream,
+            |symbol| {
+                rval.push(symbol);
+                Ok(())
+            },
+            eos_marker,
+        )?;
         Ok(rval)
     }
 
-    fn read_quantum(&self, iter: &mut dyn Iterator<Item = &u8>, mut x: u64) -> Option<u64> {
-        for i in 0..self.bytes_to_stream {
-            match iter.next() {
-                Some(&stream_byte) => {
-                    if self.verbose {
-                        println!("pulled LSB from stream {:x} {:02x}", x, stream_byte);
-                    }
-                    //token |= (stream_byte as u64) << (i * 8);
-                    x = (x << 8) | (stream_byte as u64);
-                }
-                None => {
-                    if i == 0 {
-                        return None;
-                    }
-                    break;
-                }
-            }
-        }
-        /*
-        //println!("x={:x};\ttoken {:x}", x, token);
-        if x == 0 && token == 0 {
-            if true { panic!("debug") }
-            return None;
-        }
-        Some( (x << (8*self.bytes_to_stream)) | token)*/
-        //println!("read_quantum returns {}", x);
-        Some(x)
+    /// `decode` into `out`, returning how many symbols of it were used.
+    /// The encoder's message length is the size to allocate; nothing in the stream bounds it.
+    pub fn decode_into(
+        &self,
+        stream: &[u8],
+        out: &mut [S],
+        eos_marker: u64,
+    ) -> Result<usize, AnsError> {
+        let capacity = out.len();
+        let mut used = 0;
+        self.decode_core(
+            stream,
+            |symbol| {
+                *out.get_mut(used)
+                    .ok_or(AnsError::OutputTooSmall { capacity })? = symbol;
+                used += 1;
+                Ok(())
+            },
+            eos_marker,
+        )?;
+        Ok(used)
+    }
+
+    /// `sink` receives the symbols in message order
+    fn decode_core<F>(&self, stream: &[u8], sink: F, eos_marker: u64) -> Result<(), AnsError>
+    where
+        F: FnMut(S) -> Result<(), AnsError>,
+    {
+        let eos_state = self
+            .stream_loop()
+            .initial_state::<W>(eos_marker, self.table.sum_frequencies())?;
+        self.stream_loop().decode(
+            stream,
+            eos_state,
+            &mut OneTable {
+                table: &self.table,
+                sink,
+                symbol: PhantomData,
+            },
+        )
+    }
+}
+
+/// `DecodeSteps` that decode every symbol with the same table
+struct OneTable<'a, S, T, F> {
+    table: &'a T,
+    sink: F,
+    symbol: PhantomData<S>,
+}
+
+impl<S, T, F, W> DecodeSteps<W> for OneTable<'_, S, T, F>
+where
+    S: Symbol,
+    T: UniformTable<S>,
+    F: FnMut(S) -> Result<(), AnsError>,
+    W: StateWord,
+{
+    type Symbol = S;
+
+    fn decode(&mut self, x: W) -> Result<(S, W), AnsError> {
+        let (symbol, new_x) = x.decode_step(self.table);
+        (self.sink)(symbol)?;
+        Ok((symbol, new_x))
     }
 }
 
@@ -499,7 +767,10 @@ impl StreamingANSUniform {
 
 #[cfg(test)]
 mod tests {
-    use crate::{StreamingANSUniform, SymbolFrequencies};
+    use crate::{
+        AnsError, GenericStreamingANSUniform, GenericSymbolFrequencies, NormalizationStrategy,
+        StreamingANSUniform, SymbolFrequencies,
+    };
 
     #[test]
     fn test1() {
@@ -523,4 +794,105 @@ mod tests {
             assert_eq!(orig, decoded);
         }
     }
+
+    #[test]
+    fn wide_alphabet() {
+        let orig: Vec<u16> = (0..2000u32).map(|i| ((i * i) % 300) as u16).collect();
+        let mut freqs = GenericSymbolFrequencies::<300>::new();
+        freqs.add_symbols(orig.iter().cloned());
+        let freqs = freqs
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+
+        let ansu = GenericStreamingANSUniform::<u16, 300>::new(freqs, 16, 2);
+        let encoded = ansu.encode(orig.iter().rev(), 1);
+        let decoded = ansu.decode(&encoded, 1).unwrap();
+        assert_eq!(orig, decoded);
+    }
+
+    #[test]
+    fn forward_order_entry_points() {
+        let message = b"it was the best of times, it was the worst of times".to_vec();
+        let mut freqs = SymbolFrequencies::new();
+        freqs.scan_file(&mut &message[..]).unwrap();
+        let freqs = freqs
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+        let ansu = StreamingANSUniform::new(freqs, 16, 2);
+
+        let expected = ansu.encode(message.iter().rev(), 1);
+        assert_eq!(expected, ansu.encode_slice(&message, 1).unwrap());
+        assert_eq!(
+            expected,
+            ansu.encode_iter(message.iter().cloned(), 1).unwrap()
+        );
+        assert_eq!(expected, ansu.encode_chunks(message.chunks(7), 1).unwrap());
+        let mut sunk = Vec::new();
+        ansu.encode_slice_to_sink::<AnsError>(
+            &message,
+            &mut |byte| {
+                sunk.push(byte);
+                Ok(())
+            },
+            1,
+        )
+        .unwrap();
+        assert_eq!(expected, sunk);
+        assert_eq!(message, ansu.decode(&expected, 1).unwrap());
+    }
+
+    #[test]
+    fn into_buffers() {
+        let message = b"abracadabra, abracadabra, the rarest letter is z".to_vec();
+        let mut freqs = SymbolFrequencies::new();
+        freqs.scan_file(&mut &message[..]).unwrap();
+        let freqs = freqs
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+        let ansu = StreamingANSUniform::new(freqs, 16, 2);
+
+        let expected = ansu.encode_slice(&message, 1).unwrap();
+        let mut out = vec![0; ansu.max_encoded_len(message.len(), 1)];
+        let used = ansu.encode_into(&message, &mut out, 1).unwrap();
+        assert_eq!(expected, out[..used]);
+        assert_eq!(
+            Err(AnsError::OutputTooSmall { capacity: used - 1 }),
+            ansu.encode_into(&message, &mut out[..used - 1], 1)
+        );
+
+        let mut decoded = vec![0; message.len()];
+        assert_eq!(
+            Ok(message.len()),
+            ansu.decode_into(&expected, &mut decoded, 1)
+        );
+        assert_eq!(message, decoded);
+        assert_eq!(
+            Err(AnsError::OutputTooSmall { capacity: 10 }),
+            ansu.decode_into(&expected, &mut decoded[..10], 1)
+        );
+
+        // the rarest symbol over and over is the worst case
+        for &(precision_bits, underflow_bits, bytes) in &[(12, 16, 2), (16, 16, 2), (14, 24, 2)] {
+            let mut raw = SymbolFrequencies::new();
+            raw.frequencies.iter_mut().for_each(|f| *f = 1);
+            raw.frequencies[0] = 1 << 20;
+            let freqs = raw
+                .normalize(precision_bits, NormalizationStrategy::Greedy)
+                .unwrap()
+                .frequencies;
+            let ansu = StreamingANSUniform::new(freqs, underflow_bits, bytes);
+            for len in 0..300 {
+                for &symbol in &[0, 255] {
+                    let message = vec![symbol; len];
+                    for &iv in &[1, 1 << 30] {
+                        let encoded = ansu.encode_slice(&message, iv).unwrap();
+                        assert!(encoded.len() <= ansu.max_encoded_len(len, iv));
+                    }
+                }
+            }
+        }
+    }
 }
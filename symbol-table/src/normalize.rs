@@ -0,0 +1,221 @@
+//! Scaling raw symbol counts to a table whose frequencies sum to a power of two.
+
+use crate::GenericSymbolFrequencies;
+
+pub const MAX_NORMALIZATION_BITS: u8 = 31;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalizationStrategy {
+    /// Walk the symbols from rarest to most common, giving each its proportional share of whatever
+    /// is left.  This is what the demo programs have always used.
+    Greedy,
+    /// Round every symbol independently and dump the rounding error on the most common symbols.
+    Fast,
+    /// Minimize the KL divergence between the raw counts and the normalized table,
+    /// which is the same as minimizing the expected coded length.
+    MinimumCost,
+}
+
+pub struct NormalizedFrequencies<const N: usize = 256> {
+    pub frequencies: GenericSymbolFrequencies<N>,
+    pub precision_bits: u8,
+    /// how many more bits per symbol a message with the raw distribution costs
+    /// when coded with the normalized table instead of the exact probabilities
+    pub cost_bits_per_symbol: f64,
+}
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    /// Scale the frequencies so they sum to `1<<precision_bits`.  Every symbol that was present keeps a frequency of at least 1.
+    pub fn normalize(
+        &self,
+        precision_bits: u8,
+        strategy: NormalizationStrategy,
+    ) -> Result<NormalizedFrequencies<N>, String> {
+        if precision_bits == 0 || precision_bits > MAX_NORMALIZATION_BITS {
+            return Err(format!(
+                "normalization precision {} is not in 1..={}",
+                precision_bits, MAX_NORMALIZATION_BITS
+            ));
+        }
+        let target_sum = 1u64 << precision_bits;
+        let present = self.frequencies.iter().filter(|&&f| f > 0).count() as u64;
+        if present == 0 {
+            return Err("can not normalize a table with no symbols".to_string());
+        }
+        if present > target_sum {
+            return Err(format!(
+                "{} symbols do not fit in a {}-bit table",
+                present, precision_bits
+            ));
+        }
+
+        let new_frequencies = match strategy {
+            NormalizationStrategy::Greedy => normalize_greedy(&self.frequencies, target_sum),
+            NormalizationStrategy::Fast => normalize_fast(&self.frequencies, target_sum),
+            NormalizationStrategy::MinimumCost => {
+                normalize_minimum_cost(&self.frequencies, target_sum)
+            }
+        };
+
+        assert_eq!(
+            target_sum,
+            new_frequencies.iter().map(|&f| f as u64).sum::<u64>(),
+            "{:?} normalization produced the wrong total",
+            strategy
+        );
+
+        let frequencies = GenericSymbolFrequencies {
+            frequencies: new_frequencies,
+        };
+        let cost_bits_per_symbol = normalization_cost(&self.frequencies, &frequencies.frequencies);
+        Ok(NormalizedFrequencies {
+            frequencies,
+            precision_bits,
+            cost_bits_per_symbol,
+        })
+    }
+}
+
+/// KL divergence (in bits) of the normalized table from the raw counts.
+/// Infinite if a raw symbol has no normalized frequency, which includes every symbol of an empty
+/// normalized table; 0 if there are no raw counts.
+pub fn normalization_cost(raw: &[u32], normalized: &[u32]) -> f64 {
+    let raw_sum: u64 = raw.iter().map(|&f| f as u64).sum();
+    let normalized_sum: u64 = normalized.iter().map(|&f| f as u64).sum();
+    raw.iter()
+        .zip(normalized.iter())
+        .filter(|(&count, _)| count > 0)
+        .map(|(&count, &freq)| {
+            let p = count as f64 / raw_sum as f64;
+            let q = if freq == 0 {
+                0.0
+            } else {
+                freq as f64 / normalized_sum as f64
+            };
+            p * (p / q).log2()
+        })
+        .sum()
+}
+
+fn normalize_greedy<const N: usize>(raw: &[u32; N], target_sum: u64) -> [u32; N] {
+    let mut indices: Vec<usize> = (0..raw.len()).collect();
+    indices.sort_by(|&a, &b| raw[a].cmp(&raw[b]));
+
+    let mut old_sum: u64 = raw.iter().map(|&f| f as u64).sum();
+    let mut target_sum = target_sum;
+    let mut new_frequencies = [0u32; N];
+
+    for symbol in indices {
+        let freq = raw[symbol] as u64;
+        if freq == 0 {
+            continue;
+        }
+        let new_freq = (target_sum * freq / old_sum).max(1);
+        new_frequencies[symbol] = new_freq as u32;
+
+        old_sum -= freq;
+        target_sum -= new_freq;
+    }
+    new_frequencies
+}
+
+fn normalize_fast<const N: usize>(raw: &[u32; N], target_sum: u64) -> [u32; N] {
+    let old_sum: u64 = raw.iter().map(|&f| f as u64).sum();
+    let mut new_frequencies = [0u32; N];
+    let mut new_sum = 0u64;
+    for (symbol, &freq) in raw.iter().enumerate() {
+        if freq > 0 {
+            let new_freq = ((freq as u64 * target_sum + old_sum / 2) / old_sum).max(1);
+            new_frequencies[symbol] = new_freq as u32;
+            new_sum += new_freq;
+        }
+    }
+
+    let mut by_size: Vec<usize> = (0..raw.len()).collect();
+    by_size.sort_by(|&a, &b| raw[b].cmp(&raw[a]));
+    if new_sum < target_sum {
+        new_frequencies[by_size[0]] += (target_sum - new_sum) as u32;
+    }
+    while new_sum > target_sum {
+        for &symbol in &by_size {
+            if new_sum == target_sum {
+                break;
+            }
+            if new_frequencies[symbol] > 1 {
+                new_frequencies[symbol] -= 1;
+                new_sum -= 1;
+            }
+        }
+    }
+    new_frequencies
+}
+
+/// The objective `sum(count*log(freq))` is separable and concave,
+/// so trading single units between symbols until no trade helps reaches the optimum.
+fn normalize_minimum_cost<const N: usize>(raw: &[u32; N], target_sum: u64) -> [u32; N] {
+    let mut new_frequencies = normalize_fast(raw, target_sum);
+
+    let gain =
+        |symbol: usize, freq: u32| raw[symbol] as f64 * ((freq + 1) as f64 / freq as f64).ln();
+    let loss = |symbol: usize, freq: u32| {
+        if freq <= 1 {
+            f64::INFINITY
+        } else {
+            raw[symbol] as f64 * (freq as f64 / (freq - 1) as f64).ln()
+        }
+    };
+
+    let present: Vec<usize> = (0..raw.len()).filter(|&s| raw[s] > 0).collect();
+    loop {
+        let (best_gain, grow) = present
+            .iter()
+            .map(|&s| (gain(s, new_frequencies[s]), s))
+            .fold((f64::NEG_INFINITY, 0), |a, b| if b.0 > a.0 { b } else { a });
+        let (least_loss, shrink) = present
+            .iter()
+            .map(|&s| (loss(s, new_frequencies[s]), s))
+            .fold((f64::INFINITY, 0), |a, b| if b.0 < a.0 { b } else { a });
+        if grow == shrink || best_gain <= least_loss * (1.0 + 1e-12) {
+            break;
+        }
+        new_frequencies[grow] += 1;
+        new_frequencies[shrink] -= 1;
+    }
+    new_frequencies
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{NormalizationStrategy, SymbolFrequencies};
+
+    #[test]
+    fn strategies() {
+        let mut raw = SymbolFrequencies::new();
+        for (symbol, freq) in raw.frequencies.iter_mut().enumerate().skip(30).take(90) {
+            *freq = (symbol as u32 * 7919) % 1000 + 1;
+        }
+        raw.frequencies[200] = 4_000_000_000;
+
+        let mut costs = Vec::new();
+        for &strategy in &[
+            NormalizationStrategy::Greedy,
+            NormalizationStrategy::Fast,
+            NormalizationStrategy::MinimumCost,
+        ] {
+            let normalized = raw.normalize(12, strategy).unwrap();
+            assert_eq!(
+                4096u32,
+                normalized.frequencies.frequencies.iter().sum::<u32>()
+            );
+            for (&before, &after) in raw
+                .frequencies
+                .iter()
+                .zip(normalized.frequencies.frequencies.iter())
+            {
+                assert_eq!(before > 0, after > 0);
+            }
+            costs.push(normalized.cost_bits_per_symbol);
+        }
+        assert!(costs[2] <= costs[0] && costs[2] <= costs[1], "{:?}", costs);
+    }
+}
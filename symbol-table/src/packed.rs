@@ -0,0 +1,318 @@
+//! A compact memory layout for the uniform tables, and the trait that lets the coders use either layout.
+//!
+//! `GenericANSTableUniform` keeps one heap vector of slots per symbol and a `(S, u32)` per decode slot,
+//! which the alignment pads to 8 bytes for bytes.  `GenericPackedANSTable` keeps all the slots in one array
+//! indexed by each symbol's cumulative offset, and decode entries without padding.  Both switch to `u16`
+//! when `sum_frequencies` fits, so a 12-bit byte table decodes from 12 KiB instead of 32 KiB.
+
+use crate::{
+    AnsError, Diagnostics, GenericANSTableUniform, GenericStreamingANSUniform,
+    GenericSymbolFrequencies, Reciprocal, SpreadStrategy, Symbol,
+};
+
+/// What the coders need from a table, whatever its memory layout.
+pub trait UniformTable<S: Symbol> {
+    fn alphabet_size(&self) -> usize;
+
+    fn frequency(&self, symbol: usize) -> u32;
+
+    fn sum_frequencies(&self) -> u32;
+
+    /// the largest slot `symbol` encodes to, if it has any
+    fn last_slot(&self, symbol: usize) -> Option<u32>;
+
+    fn diagnostics(&self) -> &Diagnostics;
+
+    /// the slot of `symbol`'s `phase`th state, for coders that do their own arithmetic; `phase < frequency`
+    fn encode_slot(&self, symbol: usize, phase: u32) -> u32;
+
+    /// the symbol that owns `slot`, and which of its states the slot is
+    fn decode_slot(&self, slot: u32) -> (S, u32);
+
+    fn try_append_encode64(&self, val: u64, symbol: S) -> Result<u64, AnsError>;
+
+    fn decode64(&self, val: u64) -> (S, u64);
+
+    /// Panics if `symbol` is not in the table
+    fn append_encode64(&self, val: u64, symbol: S) -> u64 {
+        self.try_append_encode64(val, symbol)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl<S: Symbol, const N: usize> UniformTable<S> for GenericANSTableUniform<S, N> {
+    fn alphabet_size(&self) -> usize {
+        N
+    }
+
+    fn frequency(&self, symbol: usize) -> u32 {
+        self.frequencies[symbol]
+    }
+
+    fn sum_frequencies(&self) -> u32 {
+        self.sum_frequencies
+    }
+
+    fn last_slot(&self, symbol: usize) -> Option<u32> {
+        // hand-written encode tables need not list their slots in order
+        self.encode[symbol].iter().max().cloned()
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn encode_slot(&self, symbol: usize, phase: u32) -> u32 {
+        self.encode[symbol][phase as usize]
+    }
+
+    fn decode_slot(&self, slot: u32) -> (S, u32) {
+        self.decode[slot as usize]
+    }
+
+    fn try_append_encode64(&self, val: u64, symbol: S) -> Result<u64, AnsError> {
+        GenericANSTableUniform::try_append_encode64(self, val, symbol)
+    }
+
+    fn decode64(&self, val: u64) -> (S, u64) {
+        GenericANSTableUniform::decode64(self, val)
+    }
+}
+
+/// `repr(packed)` so a byte symbol with a `u16` index takes 3 bytes, not 4
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct DecodeEntry<W: Copy, S: Copy> {
+    /// which of the symbol's states this slot is
+    k: W,
+    symbol: S,
+}
+
+enum Slots<S: Copy> {
+    /// `sum_frequencies <= 1<<16`
+    Narrow {
+        encode: Vec<u16>,
+        decode: Vec<DecodeEntry<u16, S>>,
+    },
+    Wide {
+        encode: Vec<u32>,
+        decode: Vec<DecodeEntry<u32, S>>,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct EncodeSymbol {
+    /// `None` for symbols that are not in the table
+    reciprocal: Option<Reciprocal>,
+    /// where the symbol's slots start in the encode array
+    offset: u32,
+}
+
+pub struct GenericPackedANSTable<S: Symbol, const N: usize> {
+    symbols: Vec<EncodeSymbol>,
+    /// for decoding, which would otherwise have to reach into `symbols`
+    frequencies: [u32; N],
+    sum_frequencies: u32,
+    sum_reciprocal: Reciprocal,
+    slots: Slots<S>,
+    pub diagnostics: Diagnostics,
+}
+
+pub type PackedANSTable = GenericPackedANSTable<u8, 256>;
+
+pub type StreamingANSPacked = GenericStreamingANSUniform<u8, 256, PackedANSTable>;
+
+impl<S: Symbol, const N: usize> GenericPackedANSTable<S, N> {
+    /// the same spread as `GenericANSTableUniform::new`
+    pub fn new(freqs: GenericSymbolFrequencies<N>) -> GenericPackedANSTable<S, N> {
+        Self::from_table(&GenericANSTableUniform::new(freqs))
+    }
+
+    pub fn with_strategy(
+        freqs: GenericSymbolFrequencies<N>,
+        strategy: &dyn SpreadStrategy,
+    ) -> Result<GenericPackedANSTable<S, N>, AnsError> {
+        Ok(Self::from_table(&GenericANSTableUniform::with_strategy(
+            freqs, strategy,
+        )?))
+    }
+
+    /// Repack a table; it codes exactly like the original.
+    pub fn from_table(table: &GenericANSTableUniform<S, N>) -> GenericPackedANSTable<S, N> {
+        let mut symbols = Vec::with_capacity(N);
+        let mut offset = 0;
+        for &frequency in table.frequencies.iter() {
+            symbols.push(EncodeSymbol {
+                reciprocal: Reciprocal::new(frequency),
+                offset,
+            });
+            offset += frequency;
+        }
+        let flat_encode = table.encode.iter().flatten().cloned();
+
+        let slots = if table.sum_frequencies <= 1 << 16 {
+            Slots::Narrow {
+                encode: flat_encode.map(|slot| slot as u16).collect(),
+                decode: table
+                    .decode
+                    .iter()
+                    .map(|&(symbol, k)| DecodeEntry {
+                        k: k as u16,
+                        symbol,
+                    })
+                    .collect(),
+            }
+        } else {
+            Slots::Wide {
+                encode: flat_encode.collect(),
+                decode: table
+                    .decode
+                    .iter()
+                    .map(|&(symbol, k)| DecodeEntry { k, symbol })
+                    .collect(),
+            }
+        };
+
+        GenericPackedANSTable {
+            symbols,
+            frequencies: table.frequencies,
+            sum_frequencies: table.sum_frequencies,
+            sum_reciprocal: table.sum_reciprocal,
+            slots,
+            diagnostics: table.diagnostics.clone(),
+        }
+    }
+
+    /// bytes of encode and decode entries, for comparing layouts
+    pub fn table_bytes(&self) -> usize {
+        match &self.slots {
+            Slots::Narrow { encode, decode } => {
+                encode.len() * 2 + decode.len() * std::mem::size_of::<DecodeEntry<u16, S>>()
+            }
+            Slots::Wide { encode, decode } => {
+                encode.len() * 4 + decode.len() * std::mem::size_of::<DecodeEntry<u32, S>>()
+            }
+        }
+    }
+
+    fn slot_at(&self, index: usize) -> u32 {
+        match &self.slots {
+            Slots::Narrow { encode, .. } => encode[index] as u32,
+            Slots::Wide { encode, .. } => encode[index],
+        }
+    }
+
+    fn decode_entry(&self, slot: usize) -> (S, u32) {
+        match &self.slots {
+            Slots::Narrow { decode, .. } => {
+                let entry = decode[slot];
+                (entry.symbol, entry.k as u32)
+            }
+            Slots::Wide { decode, .. } => {
+                let entry = decode[slot];
+                (entry.symbol, entry.k)
+            }
+        }
+    }
+}
+
+impl<S: Symbol, const N: usize> UniformTable<S> for GenericPackedANSTable<S, N> {
+    fn alphabet_size(&self) -> usize {
+        N
+    }
+
+    fn frequency(&self, symbol: usize) -> u32 {
+        self.frequencies[symbol]
+    }
+
+    fn sum_frequencies(&self) -> u32 {
+        self.sum_frequencies
+    }
+
+    fn last_slot(&self, symbol: usize) -> Option<u32> {
+        let info = &self.symbols[symbol];
+        (0..info.reciprocal?.divisor())
+            .map(|phase| self.slot_at((info.offset + phase) as usize))
+            .max()
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn encode_slot(&self, symbol: usize, phase: u32) -> u32 {
+        self.slot_at((self.symbols[symbol].offset + phase) as usize)
+    }
+
+    fn decode_slot(&self, slot: u32) -> (S, u32) {
+        self.decode_entry(slot as usize)
+    }
+
+    fn try_append_encode64(&self, val: u64, symbol: S) -> Result<u64, AnsError> {
+        let (offset, reciprocal) = match self.symbols.get(symbol.to_index()) {
+            Some(EncodeSymbol {
+                reciprocal: Some(reciprocal),
+                offset,
+            }) => (*offset, reciprocal),
+            _ => {
+                return Err(AnsError::SymbolNotInTable {
+                    symbol: symbol.to_index(),
+                })
+            }
+        };
+        let (cycle, phase) = reciprocal.div_rem(val);
+        let encoded = self.slot_at(offset as usize + phase as usize);
+        Ok(cycle * self.sum_frequencies as u64 + encoded as u64)
+    }
+
+    fn decode64(&self, val: u64) -> (S, u64) {
+        let (cycle, phase) = self.sum_reciprocal.div_rem(val);
+        let (symbol, k) = self.decode_entry(phase as usize);
+        let frequency = self.frequencies[symbol.to_index()];
+        (symbol, cycle * frequency as u64 + k as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ANSTableUniform, NormalizationStrategy, PackedANSTable, StreamingANSPacked,
+        StreamingANSUniform, SymbolFrequencies, UniformTable,
+    };
+
+    #[test]
+    fn packed_codes_like_nested() {
+        let message: Vec<u8> = b"peter piper picked a peck of pickled peppers"
+            .iter()
+            .cycle()
+            .take(5000)
+            .cloned()
+            .collect();
+        let mut raw = SymbolFrequencies::new();
+        raw.scan_file(&mut &message[..]).unwrap();
+
+        for &precision_bits in &[12, 16, 20] {
+            let freqs = raw
+                .normalize(precision_bits, NormalizationStrategy::Greedy)
+                .unwrap()
+                .frequencies;
+            let nested = ANSTableUniform::new(freqs);
+            let packed = PackedANSTable::from_table(&nested);
+            for x in (1000..3000).chain(1 << 40..(1 << 40) + 1000) {
+                assert_eq!(nested.decode64(x), packed.decode64(x));
+                for &symbol in b"pi q" {
+                    assert_eq!(
+                        nested.try_append_encode64(x, symbol),
+                        UniformTable::try_append_encode64(&packed, x, symbol)
+                    );
+                }
+            }
+
+            let expected = StreamingANSUniform::from_table(nested, 24, 2);
+            let coder = StreamingANSPacked::from_table(packed, 24, 2);
+            let encoded = coder.encode(message.iter().rev(), 1);
+            assert_eq!(expected.encode(message.iter().rev(), 1), encoded);
+            assert_eq!(message, coder.decode(&encoded, 1).unwrap());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Histogramming big inputs on several threads.  Files are memory-mapped and cut into one chunk per thread;
+//! the per-thread counts are merged at the end, so the result is identical to a serial scan.
+
+use crate::{SymbolCounts, SymbolFrequencies};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::thread;
+
+/// Inputs smaller than this are not worth starting threads for.
+const MIN_CHUNK: usize = 1 << 20;
+
+/// `threads == 0` means one per available CPU
+fn thread_count(threads: usize) -> usize {
+    if threads > 0 {
+        threads
+    } else {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    }
+}
+
+impl SymbolCounts {
+    /// Count `data` using up to `threads` threads (0 for one per CPU).
+    pub fn count_bytes_parallel(data: &[u8], threads: usize) -> SymbolCounts {
+        let chunk_size = data.len().div_ceil(thread_count(threads)).max(MIN_CHUNK);
+
+        let mut rval = SymbolCounts::new();
+        thread::scope(|scope| {
+            let workers: Vec<_> = data
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || count_chunk(chunk)))
+                .collect();
+            for worker in workers {
+                let partial = worker.join().expect("histogram thread panicked");
+                for (sum, count) in rval.counts.iter_mut().zip(partial.iter()) {
+                    *sum += count;
+                }
+            }
+        });
+        rval
+    }
+
+    /// Memory-map the file at `path` and count it with `count_bytes_parallel`.
+    /// The file must not be truncated while it is being counted.
+    pub fn scan_path_parallel(path: &Path, threads: usize) -> Result<SymbolCounts, Error> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            // mapping an empty file fails on some platforms
+            return Ok(SymbolCounts::new());
+        }
+        // Safety: nothing stops another process from changing the file while it is mapped.
+        // Changes make the counts meaningless, and truncation raises SIGBUS.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self::count_bytes_parallel(&map, threads))
+    }
+}
+
+impl SymbolFrequencies {
+    /// Like `SymbolCounts::scan_path_parallel`, but fails if any count does not fit in a `u32`.
+    pub fn scan_path_parallel(path: &Path, threads: usize) -> Result<SymbolFrequencies, Error> {
+        SymbolCounts::scan_path_parallel(path, threads)?
+            .to_frequencies()
+            .map_err(|msg| Error::new(ErrorKind::InvalidData, msg))
+    }
+}
+
+fn count_chunk(chunk: &[u8]) -> [u64; 256] {
+    // four interleaved tables keep runs of the same byte from serializing on one counter
+    let mut tables = [[0u64; 256]; 4];
+    let mut quads = chunk.chunks_exact(4);
+    for quad in &mut quads {
+        tables[0][quad[0] as usize] += 1;
+        tables[1][quad[1] as usize] += 1;
+        tables[2][quad[2] as usize] += 1;
+        tables[3][quad[3] as usize] += 1;
+    }
+    for &symbol in quads.remainder() {
+        tables[0][symbol as usize] += 1;
+    }
+
+    let mut rval = [0u64; 256];
+    for table in &tables {
+        for (sum, count) in rval.iter_mut().zip(table.iter()) {
+            *sum += count;
+        }
+    }
+    rval
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolCounts, SymbolFrequencies};
+    use std::io::Write;
+
+    #[test]
+    fn parallel_matches_serial() {
+        let mut x = 12345u32;
+        let data: Vec<u8> = (0..3_000_001)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 24) as u8 & 0x3f
+            })
+            .collect();
+
+        let mut serial = SymbolFrequencies::new();
+        serial.scan_file(&mut &data[..]).unwrap();
+
+        for &threads in &[0, 1, 3, 8] {
+            let counts = SymbolCounts::count_bytes_parallel(&data, threads);
+            assert_eq!(
+                &serial.frequencies[..],
+                &counts.to_frequencies().unwrap().frequencies[..]
+            );
+        }
+
+        let path = std::env::temp_dir().join(format!("parallel-test-{}", std::process::id()));
+        std::fs::File::create(&path)
+            .and_then(|mut f| f.write_all(&data))
+            .unwrap();
+        let mapped = SymbolFrequencies::scan_path_parallel(&path, 4);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&serial.frequencies[..], &mapped.unwrap().frequencies[..]);
+    }
+}
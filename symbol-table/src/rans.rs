@@ -0,0 +1,177 @@
+//! Range ANS (rANS) for tables normalized to `2^precision_bits`.
+//!
+//! Every symbol owns one contiguous range of slots starting at its cumulative frequency, so the table
+//! needs no encode array.  Decoding is a shift, a mask and a slot lookup; encoding multiplies by the
+//! frequency's `Reciprocal`.
+//! `StreamingRANS` is `GenericStreamingANSUniform` running over a `RANSTable`, so its byte stream is
+//! the one that coder produces from a `RangeAscending` table.
+
+use crate::{
+    table_symbol, AnsError, Diagnostics, GenericStreamingANSUniform, GenericSymbolFrequencies,
+    Reciprocal, Symbol, UniformTable,
+};
+
+pub struct GenericRANSTable<S: Symbol, const N: usize> {
+    frequencies: [u32; N],
+    reciprocals: Vec<Option<Reciprocal>>,
+    /// `cumulative[s]` is the first slot of symbol `s`
+    cumulative: [u32; N],
+    /// the symbol that owns each slot
+    slot_symbol: Vec<S>,
+    pub precision_bits: u8,
+    pub diagnostics: Diagnostics,
+}
+
+pub type RANSTable = GenericRANSTable<u8, 256>;
+
+pub type GenericStreamingRANS<S, const N: usize> =
+    GenericStreamingANSUniform<S, N, GenericRANSTable<S, N>>;
+
+pub type StreamingRANS = GenericStreamingRANS<u8, 256>;
+
+impl<S: Symbol, const N: usize> GenericRANSTable<S, N> {
+    /// `freqs` must sum to a power of two
+    pub fn new(freqs: GenericSymbolFrequencies<N>) -> Result<GenericRANSTable<S, N>, AnsError> {
+        let total = freqs.total();
+        if !total.is_power_of_two() || total > 1 << 31 {
+            return Err(AnsError::InvalidTable(format!(
+                "rANS needs frequencies that sum to a power of two up to 2^31, not {}",
+                total
+            )));
+        }
+
+        let mut cumulative = [0u32; N];
+        let mut slot_symbol = Vec::with_capacity(total as usize);
+        for (symbol, (&freq, start)) in freqs
+            .frequencies
+            .iter()
+            .zip(cumulative.iter_mut())
+            .enumerate()
+        {
+            *start = slot_symbol.len() as u32;
+            if freq > 0 {
+                let symbol = table_symbol::<S>(symbol)?;
+                slot_symbol.extend((0..freq).map(|_| symbol));
+            }
+        }
+
+        Ok(GenericRANSTable {
+            frequencies: freqs.frequencies,
+            reciprocals: freqs
+                .frequencies
+                .iter()
+                .map(|&f| Reciprocal::new(f))
+                .collect(),
+            cumulative,
+            slot_symbol,
+            precision_bits: total.trailing_zeros() as u8,
+            diagnostics: Diagnostics::default(),
+        })
+    }
+}
+
+impl<S: Symbol, const N: usize> UniformTable<S> for GenericRANSTable<S, N> {
+    fn alphabet_size(&self) -> usize {
+        N
+    }
+
+    fn frequency(&self, symbol: usize) -> u32 {
+        self.frequencies[symbol]
+    }
+
+    fn sum_frequencies(&self) -> u32 {
+        1 << self.precision_bits
+    }
+
+    fn last_slot(&self, symbol: usize) -> Option<u32> {
+        match self.frequencies[symbol] {
+            0 => None,
+            frequency => Some(self.cumulative[symbol] + frequency - 1),
+        }
+    }
+
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn encode_slot(&self, symbol: usize, phase: u32) -> u32 {
+        self.cumulative[symbol] + phase
+    }
+
+    fn decode_slot(&self, slot: u32) -> (S, u32) {
+        let symbol = self.slot_symbol[slot as usize];
+        (symbol, slot - self.cumulative[symbol.to_index()])
+    }
+
+    fn try_append_encode64(&self, val: u64, symbol: S) -> Result<u64, AnsError> {
+        let index = symbol.to_index();
+        let (cycle, phase) = self
+            .reciprocals
+            .get(index)
+            .and_then(Option::as_ref)
+            .ok_or(AnsError::SymbolNotInTable { symbol: index })?
+            .div_rem(val);
+        Ok((cycle << self.precision_bits) + self.cumulative[index] as u64 + phase)
+    }
+
+    fn decode64(&self, val: u64) -> (S, u64) {
+        let slot = (val & ((1 << self.precision_bits) - 1)) as u32;
+        let (symbol, k) = self.decode_slot(slot);
+        let frequency = self.frequencies[symbol.to_index()];
+        (
+            symbol,
+            frequency as u64 * (val >> self.precision_bits) + k as u64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ANSTableUniform, AnsError, NormalizationStrategy, RANSTable, RangeAscending,
+        StreamingANSUniform, StreamingRANS, SymbolFrequencies,
+    };
+
+    #[test]
+    fn matches_the_uniform_coder_with_range_spread() {
+        let message: Vec<u8> = b"she sells sea shells by the sea shore"
+            .iter()
+            .cycle()
+            .take(5000)
+            .cloned()
+            .collect();
+        let mut raw = SymbolFrequencies::new();
+        raw.scan_file(&mut &message[..]).unwrap();
+
+        for &precision_bits in &[8, 12, 16] {
+            let normalized = raw
+                .normalize(precision_bits, NormalizationStrategy::Greedy)
+                .unwrap();
+            let table = RANSTable::new(normalized.frequencies.clone()).unwrap();
+            let rans = StreamingRANS::from_table(table, 16, 2);
+            let encoded = rans.encode_slice(&message, 1).unwrap();
+            assert_eq!(message, rans.decode(&encoded, 1).unwrap());
+
+            let table =
+                ANSTableUniform::with_strategy(normalized.frequencies, &RangeAscending).unwrap();
+            let ansu = StreamingANSUniform::from_table(table, 16, 2);
+            assert_eq!(encoded, ansu.encode(message.iter().rev(), 1));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[0] = 3;
+        freqs.frequencies[1] = 2;
+        assert!(RANSTable::new(freqs.clone()).is_err());
+
+        freqs.frequencies[1] = 5;
+        let rans = StreamingRANS::from_table(RANSTable::new(freqs).unwrap(), 16, 2);
+        assert_eq!(
+            Some(AnsError::SymbolNotInTable { symbol: 2 }),
+            rans.encode_slice(&[2, 0], 1).err()
+        );
+        assert_eq!(Some(AnsError::ZeroInitialValue), rans.decode(&[], 0).err());
+    }
+}
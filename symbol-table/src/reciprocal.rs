@@ -0,0 +1,125 @@
+//! Division by a constant as a multiply and a shift, so the coders never divide once a table is built.
+//!
+//! For a divisor `d` with `l = ceil(log2 d)`, the multiplier `m = ceil(2^(64+l) / d)` gives
+//! `x / d == (x * m) >> (64 + l)` for every `u64` `x` (Alverson; Granlund & Montgomery).
+//! `m` needs 65 bits, so only the low 64 are stored and the implicit `2^64 * x` is added back.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reciprocal {
+    /// `m - 2^64`
+    multiplier_low: u64,
+    shift: u8,
+    divisor: u32,
+}
+
+impl Reciprocal {
+    /// `None` for a divisor of 0
+    pub fn new(divisor: u32) -> Option<Reciprocal> {
+        if divisor == 0 {
+            return None;
+        }
+        let shift = 32 - (divisor - 1).leading_zeros() as u8;
+        let d = divisor as u128;
+        let m = (1u128 << (64 + shift)).div_ceil(d);
+        Some(Reciprocal {
+            multiplier_low: (m - (1u128 << 64)) as u64,
+            shift,
+            divisor,
+        })
+    }
+
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    /// `x / divisor`
+    pub fn quotient(&self, x: u64) -> u64 {
+        let high = ((x as u128 * self.multiplier_low as u128) >> 64) + x as u128;
+        (high >> self.shift) as u64
+    }
+
+    /// `(x / divisor, x % divisor)`
+    pub fn div_rem(&self, x: u64) -> (u64, u64) {
+        let q = self.quotient(x);
+        (q, x - q * self.divisor as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GenericANSTableUniform, GenericSymbolFrequencies, Reciprocal, SpreadMethod};
+
+    #[test]
+    fn matches_division() {
+        assert_eq!(None, Reciprocal::new(0));
+        let mut divisors: Vec<u32> = (1..=1100).collect();
+        for bits in 12..32 {
+            divisors.extend(&[(1 << bits) - 1, 1 << bits, (1 << bits) + 1]);
+        }
+        divisors.extend(&[u32::MAX - 1, u32::MAX]);
+
+        for &d in &divisors {
+            let r = Reciprocal::new(d).unwrap();
+            let d64 = d as u64;
+            let edges = (1..64).flat_map(|bits| {
+                let p = 1u64 << bits;
+                vec![
+                    p - 1,
+                    p,
+                    p + 1,
+                    (p / d64) * d64,
+                    ((p / d64) * d64).wrapping_sub(1),
+                ]
+            });
+            let near_max = (0..4).flat_map(|k| vec![u64::MAX - k, (u64::MAX / d64) * d64 - k]);
+            for x in (0..3 * d64.min(2000)).chain(edges).chain(near_max) {
+                assert_eq!((x / d64, x % d64), r.div_rem(x), "{} / {}", x, d);
+            }
+        }
+    }
+
+    /// every table of 3 symbols with frequencies up to 7, against the textbook formulas
+    #[test]
+    fn tables_match_the_division_path() {
+        for packed in 1..8 * 8 * 8 {
+            let mut freqs = GenericSymbolFrequencies::<3>::new();
+            freqs.frequencies = [packed % 8, packed / 8 % 8, packed / 64];
+            let table = GenericANSTableUniform::<u8, 3>::with_strategy(
+                freqs,
+                &SpreadMethod::Precise { phase: 0 },
+            )
+            .unwrap();
+            let sum = table.sum_frequencies as u64;
+
+            let big = (1..64).flat_map(|bits| vec![(1u64 << bits) - 1, 1 << bits]);
+            for x in (0..40 * sum).chain(big) {
+                for symbol in 0..3u8 {
+                    let freq = table.frequencies[symbol as usize] as u64;
+                    if freq == 0 {
+                        assert!(table.try_append_encode64(x, symbol).is_err());
+                        continue;
+                    }
+                    let encoded = table.encode[symbol as usize][(x % freq) as usize] as u64;
+                    let expected = match (x / freq).checked_mul(sum) {
+                        Some(cycle) if cycle.checked_add(encoded).is_some() => cycle + encoded,
+                        _ => continue,
+                    };
+                    assert_eq!(expected, table.append_encode64(x, symbol));
+                    if x < 1 << 26 {
+                        let expected32 = expected as u32;
+                        assert_eq!(expected32, table.append_encode32(x as u32, symbol));
+                    }
+                }
+
+                let (symbol, k) = table.decode[(x % sum) as usize];
+                let freq = table.frequencies[symbol as usize] as u64;
+                let expected = (symbol, (x / sum) * freq + k as u64);
+                assert_eq!(expected, table.decode64(x));
+                if x < 1 << 32 {
+                    let (symbol32, x32) = table.decode32(x as u32);
+                    assert_eq!((expected.0, expected.1 as u32), (symbol32, x32));
+                }
+            }
+        }
+    }
+}
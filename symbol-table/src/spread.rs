@@ -0,0 +1,448 @@
+//! Symbol spreading: deciding which symbol owns each of the `sum_frequencies` slots of a table.
+//!
+//! The encode and decode tables follow mechanically from the spread, so any spread in which every
+//! symbol owns exactly `frequency` slots gives a working coder.  They differ in speed and in how much
+//! the coded size deviates from the entropy.
+//!
+//! `SpreadMethod` covers the fast general-purpose spreads.  The other `SpreadStrategy` implementations
+//! are the hand-built layouts the ordering experiments compare against.
+
+use crate::{
+    table_symbol, AnsError, Diagnostics, GenericANSTableUniform, GenericSymbolFrequencies,
+    Reciprocal, Symbol,
+};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::convert::TryFrom;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpreadMethod {
+    /// `build_tables`: every slot, every symbol adds its frequency to an accumulator that starts at `phase`
+    /// and claims the slot when it overflows.  O(L·N), and slow for big tables.
+    Accumulator { phase: u32 },
+    /// The same spread as `Accumulator`, computed with a heap of each symbol's next overflow in O(L log N).
+    Precise { phase: u32 },
+    /// FSE-style: walk the table with a fixed step coprime to its size, giving each symbol consecutive stops.
+    /// O(L), but the symbols are not spread as evenly.
+    Step,
+}
+
+/// Decides which slots each symbol's states encode to.
+pub trait SpreadStrategy {
+    /// `encode[symbol][k]` is the slot for the `k`th state of `symbol`: `frequencies[symbol]` distinct
+    /// slots below `sum(frequencies)` per symbol, every slot used exactly once.
+    fn encode_table(&self, frequencies: &[u32]) -> Result<Vec<Vec<u32>>, AnsError>;
+}
+
+impl SpreadStrategy for SpreadMethod {
+    fn encode_table(&self, frequencies: &[u32]) -> Result<Vec<Vec<u32>>, AnsError> {
+        Ok(encode_from_spread(
+            &self.spread(frequencies)?,
+            frequencies.len(),
+        ))
+    }
+}
+
+/// Each symbol gets one contiguous block of slots, symbol 0 first.
+pub struct RangeAscending;
+
+impl SpreadStrategy for RangeAscending {
+    fn encode_table(&self, frequencies: &[u32]) -> Result<Vec<Vec<u32>>, AnsError> {
+        checked_sum(frequencies)?;
+        let mut cursor = 0;
+        Ok(frequencies
+            .iter()
+            .map(|&freq| {
+                let block = (cursor..cursor + freq).collect();
+                cursor += freq;
+                block
+            })
+            .collect())
+    }
+}
+
+/// Each symbol gets one contiguous block of slots, symbol 0 last.  The slots within a block still ascend.
+pub struct RangeDescending;
+
+impl SpreadStrategy for RangeDescending {
+    fn encode_table(&self, frequencies: &[u32]) -> Result<Vec<Vec<u32>>, AnsError> {
+        let mut cursor = checked_sum(frequencies)?;
+        Ok(frequencies
+            .iter()
+            .map(|&freq| {
+                cursor -= freq;
+                (cursor..cursor + freq).collect()
+            })
+            .collect())
+    }
+}
+
+/// Another strategy mirrored end for end: slot `i` becomes slot `L-1-i`.
+pub struct Flipped<T: SpreadStrategy>(pub T);
+
+impl<T: SpreadStrategy> SpreadStrategy for Flipped<T> {
+    fn encode_table(&self, frequencies: &[u32]) -> Result<Vec<Vec<u32>>, AnsError> {
+        let sum_frequencies = checked_sum(frequencies)?;
+        let mut encode = self.0.encode_table(frequencies)?;
+        for per_symbol in encode.iter_mut() {
+            for next in per_symbol.iter_mut() {
+                *next = sum_frequencies.checked_sub(*next + 1).ok_or_else(|| {
+                    AnsError::InvalidTable(format!(
+                        "slot {} is past the end of a table of {}",
+                        next, sum_frequencies
+                    ))
+                })?;
+            }
+            per_symbol.sort_unstable();
+        }
+        Ok(encode)
+    }
+}
+
+/// A hand-written encode table.  Symbols past the end of the list have no slots.
+pub struct ExplicitEncodeTable(pub Vec<Vec<u32>>);
+
+impl SpreadStrategy for ExplicitEncodeTable {
+    fn encode_table(&self, _frequencies: &[u32]) -> Result<Vec<Vec<u32>>, AnsError> {
+        Ok(self.0.clone())
+    }
+}
+
+impl SpreadMethod {
+    /// The symbol index that owns each slot.  `frequencies` must not sum to more than `u32::MAX`,
+    /// and `phase` must be less than the sum.
+    pub fn spread(&self, frequencies: &[u32]) -> Result<Vec<usize>, AnsError> {
+        let sum_frequencies = checked_sum(frequencies)?;
+        if let SpreadMethod::Accumulator { phase } | SpreadMethod::Precise { phase } = *self {
+            if phase > 0 && phase >= sum_frequencies {
+                return Err(AnsError::InvalidTable(format!(
+                    "phase {} must be less than sum_frequencies {}",
+                    phase, sum_frequencies
+                )));
+            }
+        }
+        Ok(match *self {
+            SpreadMethod::Accumulator { phase } => spread_accumulator(frequencies, phase),
+            SpreadMethod::Precise { phase } => spread_precise(frequencies, phase),
+            SpreadMethod::Step => spread_step(frequencies),
+        })
+    }
+}
+
+/// The accumulators run up to twice `sum_frequencies`, so they are u64.
+fn spread_accumulator(frequencies: &[u32], phase: u32) -> Vec<usize> {
+    let sum_frequencies: u64 = frequencies.iter().map(|&f| f as u64).sum();
+    let mut accum = vec![phase as u64; frequencies.len()];
+    let mut rval = Vec::with_capacity(sum_frequencies as usize);
+    for _i in 0..sum_frequencies {
+        for (symbol, &freq) in frequencies.iter().enumerate() {
+            accum[symbol] += freq as u64;
+            if accum[symbol] >= sum_frequencies {
+                rval.push(symbol);
+                accum[symbol] -= sum_frequencies;
+            }
+        }
+    }
+    rval
+}
+
+/// After `i+1` rounds a symbol's accumulator has overflowed `floor((phase + (i+1)*freq) / L)` times,
+/// so its `k`th slot is claimed in round `ceil(((k+1)*L - phase) / freq) - 1`.
+/// Within a round the accumulator visits symbols in order, which the heap reproduces by breaking ties on the symbol.
+fn spread_precise(frequencies: &[u32], phase: u32) -> Vec<usize> {
+    let sum_frequencies: u64 = frequencies.iter().map(|&f| f as u64).sum();
+    let round = |symbol: usize, k: u64| {
+        let freq = frequencies[symbol] as u64;
+        ((k + 1) * sum_frequencies - phase as u64).div_ceil(freq) - 1
+    };
+
+    let mut heap: BinaryHeap<Reverse<(u64, usize, u64)>> = frequencies
+        .iter()
+        .enumerate()
+        .filter(|(_, &freq)| freq > 0)
+        .map(|(symbol, _)| Reverse((round(symbol, 0), symbol, 0)))
+        .collect();
+
+    let mut rval = Vec::with_capacity(sum_frequencies as usize);
+    while let Some(Reverse((_, symbol, k))) = heap.pop() {
+        rval.push(symbol);
+        if k + 1 < frequencies[symbol] as u64 {
+            heap.push(Reverse((round(symbol, k + 1), symbol, k + 1)));
+        }
+    }
+    rval
+}
+
+fn spread_step(frequencies: &[u32]) -> Vec<usize> {
+    let sum_frequencies: usize = frequencies.iter().map(|&f| f as usize).sum();
+    if sum_frequencies == 0 {
+        return Vec::new();
+    }
+    let mut step = (sum_frequencies >> 1) + (sum_frequencies >> 3) + 3;
+    while gcd(step, sum_frequencies) != 1 {
+        step += 1;
+    }
+
+    let mut rval = vec![0; sum_frequencies];
+    let mut position = 0;
+    for (symbol, &freq) in frequencies.iter().enumerate() {
+        for _ in 0..freq {
+            rval[position] = symbol;
+            position = (position + step) % sum_frequencies;
+        }
+    }
+    rval
+}
+
+fn checked_sum(frequencies: &[u32]) -> Result<u32, AnsError> {
+    frequencies
+        .iter()
+        .try_fold(0u32, |sum, &freq| sum.checked_add(freq))
+        .ok_or_else(|| AnsError::InvalidTable("frequencies sum to more than a u32".into()))
+}
+
+fn encode_from_spread(spread: &[usize], alphabet_size: usize) -> Vec<Vec<u32>> {
+    let mut encode = vec![Vec::new(); alphabet_size];
+    for (slot, &symbol) in spread.iter().enumerate() {
+        encode[symbol].push(slot as u32);
+    }
+    encode
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl<S: Symbol, const N: usize> GenericANSTableUniform<S, N> {
+    pub fn with_spread(
+        freqs: GenericSymbolFrequencies<N>,
+        method: SpreadMethod,
+    ) -> GenericANSTableUniform<S, N> {
+        Self::with_strategy(freqs, &method)
+            .unwrap_or_else(|e| panic!("{:?} spread is broken: {}", method, e))
+    }
+
+    /// Build the encode table with `strategy` and the decode table to match.
+    /// Fails if the strategy did not produce a permutation of the slots.
+    pub fn with_strategy(
+        freqs: GenericSymbolFrequencies<N>,
+        strategy: &dyn SpreadStrategy,
+    ) -> Result<GenericANSTableUniform<S, N>, AnsError> {
+        let encode = strategy.encode_table(&freqs.frequencies)?;
+        Self::from_encode_table(freqs, encode)
+    }
+
+    /// The checked way to build a table by hand: `encode[symbol]` must list `frequencies[symbol]` slots,
+    /// and together the lists must use every slot in `0..sum_frequencies` exactly once.
+    /// A table without any symbols is rejected, because nothing could be decoded with it.
+    /// Symbols past the end of `encode` have no slots.  The decode table is derived from `encode`.
+    pub fn from_encode_table(
+        freqs: GenericSymbolFrequencies<N>,
+        mut encode: Vec<Vec<u32>>,
+    ) -> Result<GenericANSTableUniform<S, N>, AnsError> {
+        let frequencies = freqs.frequencies;
+        let sum_frequencies = u32::try_from(freqs.total()).map_err(|_| {
+            AnsError::InvalidTable(format!(
+                "frequencies sum to {}, more than a u32",
+                freqs.total()
+            ))
+        })?;
+        if encode.len() > N {
+            return Err(AnsError::InvalidTable(format!(
+                "encode table has {} symbols, but the alphabet only has {}",
+                encode.len(),
+                N
+            )));
+        }
+        encode.resize(N, Vec::new());
+        for (symbol, (slots, &freq)) in encode.iter().zip(frequencies.iter()).enumerate() {
+            if slots.len() != freq as usize {
+                return Err(AnsError::InvalidTable(format!(
+                    "symbol {} has {} slots but a frequency of {}",
+                    symbol,
+                    slots.len(),
+                    freq
+                )));
+            }
+        }
+        let sum_reciprocal = Reciprocal::new(sum_frequencies)
+            .ok_or_else(|| AnsError::InvalidTable("a table needs at least one symbol".into()))?;
+        let decode = Self::decode_from_encode(&encode, sum_frequencies)?;
+        Ok(GenericANSTableUniform {
+            frequencies,
+            sum_frequencies,
+            encode,
+            decode,
+            verbose: false,
+            diagnostics: Diagnostics::default(),
+            reciprocals: frequencies.iter().map(|&f| Reciprocal::new(f)).collect(),
+            sum_reciprocal,
+        })
+    }
+
+    /// Invert an encode table: `decode[encode[symbol][k]] == (symbol, k)`.
+    pub fn decode_from_encode(
+        encode: &[Vec<u32>],
+        sum_frequencies: u32,
+    ) -> Result<Vec<(S, u32)>, AnsError> {
+        let mut decode = vec![None; sum_frequencies as usize];
+        for (symbol, slots) in encode.iter().enumerate() {
+            for (k, &slot) in slots.iter().enumerate() {
+                match decode.get_mut(slot as usize) {
+                    None => {
+                        return Err(AnsError::InvalidTable(format!(
+                            "symbol {} uses slot {}, past the end of a table of {}",
+                            symbol, slot, sum_frequencies
+                        )))
+                    }
+                    Some(Some((other, _))) => {
+                        return Err(AnsError::InvalidTable(format!(
+                            "slot {} is used by symbols {:?} and {}",
+                            slot, other, symbol
+                        )))
+                    }
+                    Some(entry) => *entry = Some((table_symbol(symbol)?, k as u32)),
+                }
+            }
+        }
+        decode
+            .into_iter()
+            .enumerate()
+            .map(|(slot, entry)| {
+                entry.ok_or_else(|| AnsError::InvalidTable(format!("no symbol uses slot {}", slot)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ANSTableUniform, AnsError, ExplicitEncodeTable, Flipped, NormalizationStrategy,
+        PackedANSTable, RangeAscending, RangeDescending, SpreadMethod, SpreadStrategy,
+        StreamingANSUniform, SymbolFrequencies, UniformTable,
+    };
+
+    #[test]
+    fn every_method_is_a_permutation() {
+        let mut raw = SymbolFrequencies::new();
+        for (symbol, freq) in raw.frequencies.iter_mut().enumerate().take(40) {
+            *freq = (symbol as u32 * 37) % 101 + 1;
+        }
+        let freqs = raw
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+        let sum_frequencies: u32 = freqs.frequencies.iter().sum();
+
+        for &method in &[
+            SpreadMethod::Accumulator { phase: 0 },
+            SpreadMethod::Accumulator { phase: 17 },
+            SpreadMethod::Precise { phase: 0 },
+            SpreadMethod::Precise { phase: 17 },
+            SpreadMethod::Step,
+        ] {
+            let table = ANSTableUniform::with_spread(freqs.clone(), method);
+            let mut seen = vec![false; sum_frequencies as usize];
+            for (symbol, slots) in table.encode.iter().enumerate() {
+                assert_eq!(freqs.frequencies[symbol] as usize, slots.len());
+                for (k, &slot) in slots.iter().enumerate() {
+                    assert!(!seen[slot as usize], "{:?} reused slot {}", method, slot);
+                    seen[slot as usize] = true;
+                    assert_eq!((symbol as u8, k as u32), table.decode[slot as usize]);
+                }
+            }
+
+            let message: Vec<u8> = (0..5000u32).map(|i| ((i * i) % 40) as u8).collect();
+            let coder = StreamingANSUniform::from_table(table, 16, 2);
+            let encoded = coder.encode(message.iter().rev(), 1);
+            assert_eq!(message, coder.decode(&encoded, 1).unwrap(), "{:?}", method);
+        }
+
+        for phase in [0, 1, sum_frequencies / 2, sum_frequencies - 1] {
+            assert_eq!(
+                SpreadMethod::Accumulator { phase }.spread(&freqs.frequencies),
+                SpreadMethod::Precise { phase }.spread(&freqs.frequencies)
+            );
+        }
+    }
+
+    #[test]
+    fn strategies_build_decode_tables() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[..4].copy_from_slice(&[1, 2, 4, 8]);
+        let strategies: Vec<Box<dyn SpreadStrategy>> = vec![
+            Box::new(RangeAscending),
+            Box::new(RangeDescending),
+            Box::new(Flipped(SpreadMethod::Accumulator { phase: 7 })),
+            Box::new(ExplicitEncodeTable(vec![
+                vec![0],
+                vec![5, 9],
+                vec![2, 7, 11, 13],
+                vec![1, 3, 4, 6, 8, 10, 12, 14],
+            ])),
+        ];
+        for strategy in &strategies {
+            let table = ANSTableUniform::with_strategy(freqs.clone(), strategy.as_ref()).unwrap();
+            let message: Vec<u8> = (0..20u32).map(|i| (i % 7 % 4) as u8).collect();
+            let mut x = 1u64;
+            for &symbol in message.iter().rev() {
+                x = table.append_encode64(x, symbol);
+            }
+            let mut decoded = Vec::new();
+            while x != 1 {
+                let (symbol, new_x) = table.decode64(x);
+                decoded.push(symbol);
+                x = new_x;
+            }
+            assert_eq!(message, decoded);
+        }
+
+        let overlapping = ExplicitEncodeTable(vec![vec![0], vec![0, 1]]);
+        let mut small = SymbolFrequencies::new();
+        small.frequencies[..2].copy_from_slice(&[1, 2]);
+        assert!(ANSTableUniform::with_strategy(small, &overlapping).is_err());
+    }
+
+    #[test]
+    fn from_encode_table() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[0] = 3;
+        freqs.frequencies[1] = 1;
+
+        let table = ANSTableUniform::from_encode_table(freqs.clone(), vec![vec![1, 2, 3], vec![0]])
+            .unwrap();
+        assert_eq!(vec![(1, 0), (0, 0), (0, 1), (0, 2)], table.decode);
+
+        // slots in any order, as long as the decode table matches
+        let table = ANSTableUniform::from_encode_table(freqs.clone(), vec![vec![3, 1, 2], vec![0]])
+            .unwrap();
+        assert_eq!(vec![(1, 0), (0, 1), (0, 2), (0, 0)], table.decode);
+        assert_eq!(Some(3), table.last_slot(0));
+        assert_eq!(Some(3), PackedANSTable::from_table(&table).last_slot(0));
+
+        for (encode, error) in [
+            (
+                vec![vec![1, 2], vec![0]],
+                "symbol 0 has 2 slots but a frequency of 3",
+            ),
+            (
+                vec![vec![1, 2, 4], vec![0]],
+                "symbol 0 uses slot 4, past the end of a table of 4",
+            ),
+            (
+                vec![vec![0, 1, 2], vec![2]],
+                "slot 2 is used by symbols 0 and 1",
+            ),
+        ] {
+            assert_eq!(
+                Some(AnsError::InvalidTable(error.to_string())),
+                ANSTableUniform::from_encode_table(freqs.clone(), encode).err()
+            );
+        }
+    }
+}
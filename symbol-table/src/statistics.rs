@@ -0,0 +1,89 @@
+//! Information-theoretic measurements of frequency tables, all in bits.
+//!
+//! A table can play two roles: the histogram of a message, or the model a coder uses for it.
+//! `cross_entropy` and `kl_divergence` take the histogram as `self` and the model as the argument.
+
+use crate::{normalization_cost, GenericSymbolFrequencies};
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    /// Shannon entropy in bits per symbol; 0 for an empty table
+    pub fn entropy(&self) -> f64 {
+        self.cross_entropy(self)
+    }
+
+    /// `-log2(p)` for every symbol: what a perfect entropy coder spends on it.
+    /// Symbols with a frequency of 0 can not be coded and get `f64::INFINITY`.
+    pub fn code_lengths(&self) -> [f64; N] {
+        let total = self.total() as f64;
+        let mut rval = [f64::INFINITY; N];
+        for (length, &freq) in rval.iter_mut().zip(self.frequencies.iter()) {
+            if freq > 0 {
+                *length = (total / freq as f64).log2();
+            }
+        }
+        rval
+    }
+
+    /// Bits per symbol to code a message with this histogram using `model`.
+    /// Infinite if the message contains a symbol `model` can not code.
+    pub fn cross_entropy(&self, model: &GenericSymbolFrequencies<N>) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+        let lengths = model.code_lengths();
+        self.frequencies
+            .iter()
+            .zip(lengths.iter())
+            .filter(|(&count, _)| count > 0)
+            .map(|(&count, &length)| count as f64 / total as f64 * length)
+            .sum()
+    }
+
+    /// How many bits per symbol are wasted by coding this histogram with `model` instead of itself.
+    /// Normalizing is the same question, so this is `normalization_cost` with `self` as the raw counts.
+    /// Like `cross_entropy`, infinite if the message contains a symbol `model` can not code (every
+    /// symbol, for an empty model) and 0 for an empty histogram.
+    pub fn kl_divergence(&self, model: &GenericSymbolFrequencies<N>) -> f64 {
+        normalization_cost(&self.frequencies, &model.frequencies)
+    }
+
+    /// Size in bits of this whole histogram coded with `model`, ignoring the coder's own overhead
+    pub fn predicted_size_bits(&self, model: &GenericSymbolFrequencies<N>) -> f64 {
+        self.cross_entropy(model) * self.total() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{normalization_cost, SymbolFrequencies};
+
+    #[test]
+    fn statistics() {
+        let mut message = SymbolFrequencies::new();
+        message.frequencies[b'a' as usize] = 2;
+        message.frequencies[b'b' as usize] = 1;
+        message.frequencies[b'c' as usize] = 1;
+        assert_eq!(1.5, message.entropy());
+        assert_eq!(1.0, message.code_lengths()[b'a' as usize]);
+        assert_eq!(f64::INFINITY, message.code_lengths()[b'd' as usize]);
+
+        let mut flat = SymbolFrequencies::new();
+        flat.add_symbols(b"abcd".iter().cloned());
+        assert_eq!(2.0, message.cross_entropy(&flat));
+        assert_eq!(8.0, message.predicted_size_bits(&flat));
+        assert_eq!(0.5, message.kl_divergence(&flat));
+        assert_eq!(
+            normalization_cost(&message.frequencies, &flat.frequencies),
+            message.kl_divergence(&flat)
+        );
+        assert_eq!(0.0, message.kl_divergence(&message));
+        assert_eq!(f64::INFINITY, flat.cross_entropy(&message));
+        assert_eq!(f64::INFINITY, flat.kl_divergence(&message));
+
+        let empty = SymbolFrequencies::new();
+        assert_eq!(f64::INFINITY, message.kl_divergence(&empty));
+        assert_eq!(0.0, empty.kl_divergence(&empty));
+        assert_eq!(0.0, empty.kl_divergence(&message));
+    }
+}
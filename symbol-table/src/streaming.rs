@@ -0,0 +1,749 @@
+//! The encode and decode loops every streaming coder shares, with the state integer and the
+//! renormalization quantum as parameters.
+//!
+//! The encoder keeps its state below `1<<(underflow_bits + quantum_bits)`: when a symbol would push it
+//! past that, the low `quantum_bits` of the state are streamed first.  After the message the whole state
+//! is streamed, and the decoder reads the stream from the end, taking a quantum back whenever its state
+//! drops below `1<<underflow_bits`.
+//!
+//! Quanta that are not whole bytes are packed least significant bit first.  Only quanta that divide a
+//! byte or are whole bytes are allowed, which makes the padding of the last byte a run of zero quanta
+//! that the decoder reads into a zero state and ignores.
+//!
+//! `GenericStreamingANSUniform` runs the loops over one table; the escape and context coders pick a
+//! table for every symbol.
+
+use crate::{AnsError, Diagnostics, Symbol, UniformTable};
+use std::convert::TryFrom;
+use std::fmt::{Debug, LowerHex};
+use std::ops::{BitOr, Shl, Shr};
+
+/// An unsigned integer the streaming coder can keep its state in.
+pub trait StateWord:
+    Copy
+    + Eq
+    + Ord
+    + Debug
+    + LowerHex
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+    + BitOr<Output = Self>
+{
+    const BITS: u32;
+    const ZERO: Self;
+
+    fn from_u32(x: u32) -> Self;
+
+    fn try_from_u64(x: u64) -> Option<Self>;
+
+    /// the low 32 bits
+    fn low_u32(self) -> u32;
+
+    /// `(self / divisor, self % divisor)`
+    fn div_rem(self, divisor: u32) -> (Self, u32);
+
+    /// `self * m + a`; the balance check makes sure this does not overflow
+    fn mul_add(self, m: u32, a: u32) -> Self;
+
+    /// Encode `symbol` onto the state with `table`.
+    fn append_encode<S: Symbol, T: UniformTable<S>>(
+        self,
+        table: &T,
+        symbol: S,
+    ) -> Result<Self, AnsError> {
+        let index = symbol.to_index();
+        let frequency = if index < table.alphabet_size() {
+            table.frequency(index)
+        } else {
+            0
+        };
+        if frequency == 0 {
+            return Err(AnsError::SymbolNotInTable { symbol: index });
+        }
+        let (cycle, phase) = self.div_rem(frequency);
+        Ok(cycle.mul_add(table.sum_frequencies(), table.encode_slot(index, phase)))
+    }
+
+    /// Decode the last symbol encoded onto the state with `table`, and the state before it.
+    fn decode_step<S: Symbol, T: UniformTable<S>>(self, table: &T) -> (S, Self) {
+        let (cycle, slot) = self.div_rem(table.sum_frequencies());
+        let (symbol, k) = table.decode_slot(slot);
+        (symbol, cycle.mul_add(table.frequency(symbol.to_index()), k))
+    }
+}
+
+macro_rules! state_word_arithmetic {
+    ($t:ty) => {
+        const BITS: u32 = <$t>::BITS;
+        const ZERO: Self = 0;
+
+        fn from_u32(x: u32) -> Self {
+            x as $t
+        }
+
+        fn try_from_u64(x: u64) -> Option<Self> {
+            <$t>::try_from(x).ok()
+        }
+
+        fn low_u32(self) -> u32 {
+            self as u32
+        }
+
+        fn div_rem(self, divisor: u32) -> (Self, u32) {
+            let divisor = divisor as $t;
+            (self / divisor, (self % divisor) as u32)
+        }
+
+        fn mul_add(self, m: u32, a: u32) -> Self {
+            self * m as $t + a as $t
+        }
+    };
+}
+
+/// The table's own 64-bit arithmetic; the balance check keeps every result below `1<<32`.
+impl StateWord for u32 {
+    state_word_arithmetic!(u32);
+
+    fn append_encode<S: Symbol, T: UniformTable<S>>(
+        self,
+        table: &T,
+        symbol: S,
+    ) -> Result<Self, AnsError> {
+        Ok(table.try_append_encode64(self as u64, symbol)? as u32)
+    }
+
+    fn decode_step<S: Symbol, T: UniformTable<S>>(self, table: &T) -> (S, Self) {
+        let (symbol, x) = table.decode64(self as u64);
+        (symbol, x as u32)
+    }
+}
+
+/// The table's own arithmetic, which divides by multiplying with reciprocals.
+impl StateWord for u64 {
+    state_word_arithmetic!(u64);
+
+    fn append_encode<S: Symbol, T: UniformTable<S>>(
+        self,
+        table: &T,
+        symbol: S,
+    ) -> Result<Self, AnsError> {
+        table.try_append_encode64(self, symbol)
+    }
+
+    fn decode_step<S: Symbol, T: UniformTable<S>>(self, table: &T) -> (S, Self) {
+        table.decode64(self)
+    }
+}
+
+impl StateWord for u128 {
+    state_word_arithmetic!(u128);
+}
+
+/// Fails if a `W` state streaming `quantum_bits` at a time could overflow while encoding with `table`.
+/// Symbols rare enough to make the stream inefficient are only reported to `table.diagnostics()`.
+pub fn check_stream_balance<W: StateWord, S: Symbol, T: UniformTable<S>>(
+    table: &T,
+    underflow_bits: u8,
+    quantum_bits: u8,
+) -> Result<(), AnsError> {
+    let (underflow_bits, quantum_bits) = (underflow_bits as u32, quantum_bits as u32);
+    if quantum_bits == 0 || quantum_bits > 32 || (8 % quantum_bits != 0 && quantum_bits % 8 != 0) {
+        return Err(AnsError::Unbalanced(format!(
+            "a quantum of {} bits is not 1, 2, 4, 8, 16, 24 or 32",
+            quantum_bits
+        )));
+    }
+    if underflow_bits < quantum_bits {
+        return Err(AnsError::Unbalanced(format!(
+            "underflow_bits {} is too small ( < {} )",
+            underflow_bits, quantum_bits
+        )));
+    }
+
+    let max_result_bits = underflow_bits + 2 * quantum_bits;
+    if W::BITS < max_result_bits {
+        return Err(AnsError::Unbalanced(format!(
+            "encoding process will probably overflow ( {} < {} + 2*{} )",
+            W::BITS,
+            underflow_bits,
+            quantum_bits
+        )));
+    }
+
+    let sum_frequencies = table.sum_frequencies();
+    if sum_frequencies as u128 > 1 << underflow_bits {
+        return Err(AnsError::Unbalanced(format!(
+            "sum_frequencies {} is bigger than 1<<underflow_bits {}",
+            sum_frequencies, underflow_bits
+        )));
+    }
+
+    // u128 holds everything up to here except a u128 state that uses all its bits
+    let max_working_x = u128::MAX >> (128 - (underflow_bits + quantum_bits));
+    for symbol in 0..table.alphabet_size() {
+        let frequency = table.frequency(symbol);
+        if frequency == 0 {
+            continue;
+        }
+        if frequency >= sum_frequencies {
+            return Err(AnsError::InvalidTable(format!(
+                "symbol {} owns every slot, so coding it would not change the state",
+                symbol
+            )));
+        }
+        if sum_frequencies as u128 > (frequency as u128) << quantum_bits {
+            table.diagnostics().emit(&format!("symbol {} frequency is small enough that encoding could jump by too many bits ( {} > {} << {} )",
+                     symbol, sum_frequencies, frequency, quantum_bits));
+        }
+
+        let cycle = max_working_x / (frequency as u128);
+        let jump = table.last_slot(symbol).ok_or_else(|| {
+            AnsError::InvalidTable(format!("symbol {} has no encode table", symbol))
+        })?;
+        let fits = cycle
+            .checked_mul(sum_frequencies as u128)
+            .and_then(|x| x.checked_add(jump as u128))
+            .is_some_and(|x2| max_result_bits >= 128 || x2 >> max_result_bits == 0);
+        if !fits {
+            return Err(AnsError::Unbalanced(format!("symbol {} frequency is small enough that encoding could jump past {} bits from {:x}",
+                   symbol, max_result_bits, max_working_x)));
+        }
+        check_after_push(table, symbol, jump, underflow_bits, quantum_bits)?;
+    }
+    Ok(())
+}
+
+/// After a quantum is pushed, the symbol has to land the state back in
+/// `[1<<underflow_bits, 1<<(underflow_bits + quantum_bits))`, or the decoder would read the wrong
+/// number of quanta there.
+fn check_after_push<S: Symbol, T: UniformTable<S>>(
+    table: &T,
+    symbol: usize,
+    last_slot: u32,
+    underflow_bits: u32,
+    quantum_bits: u32,
+) -> Result<(), AnsError> {
+    let (frequency, sum_frequencies) = (
+        table.frequency(symbol) as u128,
+        table.sum_frequencies() as u128,
+    );
+    let encode = |x: u128| {
+        (x / frequency) * sum_frequencies
+            + table.encode_slot(symbol, (x % frequency) as u32) as u128
+    };
+    let (underflow, overflow) = (
+        1u128 << underflow_bits,
+        1u128 << (underflow_bits + quantum_bits),
+    );
+
+    // `encode(x + frequency) == encode(x) + sum_frequencies`, so one cycle of states below `underflow`
+    // holds the highest result
+    let highest = (underflow - frequency..underflow)
+        .map(encode)
+        .max()
+        .unwrap_or(0);
+    // the first cycle of states that can overflow overflows only in some slots, and every later cycle
+    // overflows in all of them
+    let first_cycle = (overflow - last_slot as u128).div_ceil(sum_frequencies) * frequency;
+    let later_cycles = first_cycle + frequency;
+    let later_pushed = later_cycles >> quantum_bits;
+    let lowest = (first_cycle..later_cycles)
+        .filter(|&x| encode(x) >= overflow)
+        .map(|x| x >> quantum_bits)
+        .chain(later_pushed..later_pushed + frequency)
+        .map(encode)
+        .min()
+        .unwrap_or(0);
+    if lowest < underflow || highest >= overflow {
+        return Err(AnsError::Unbalanced(format!(
+            "symbol {} can leave [{:x}, {:x}) after a quantum is pushed",
+            symbol, underflow, overflow
+        )));
+    }
+    Ok(())
+}
+
+/// The parameters of the shared loops, and where their traces go.
+pub(crate) struct StreamLoop<'a> {
+    pub underflow_bits: u8,
+    pub quantum_bits: u8,
+    /// `Some` when the coder is `verbose`
+    pub trace: Option<&'a Diagnostics>,
+}
+
+/// lets `encode_to_sink` panic on coder errors while passing sink errors through
+pub(crate) enum EncodeFailure<E> {
+    Sink(E),
+    Coder(AnsError),
+}
+
+/// What the decoder does with each state: which table decodes it, and where the symbol goes.
+pub(crate) trait DecodeSteps<W> {
+    /// what the traces show of a decoded symbol
+    type Symbol: Debug;
+
+    /// Decode a symbol from `x` and keep it.  Returns the symbol and the state before it was encoded.
+    fn decode(&mut self, x: W) -> Result<(Self::Symbol, W), AnsError>;
+
+    /// `false` where the message can not end, like between an `ESCAPE` and its literal
+    fn at_boundary(&self) -> bool {
+        true
+    }
+}
+
+impl StreamLoop<'_> {
+    /// The state `encode` starts from and `decode` stops at.
+    ///
+    /// States below `sum_frequencies` can be fixed points of `append_encode` (the symbol would be encoded
+    /// without changing the state), so the coders start above them.  The state also has to start below
+    /// the point where a quantum is pushed.
+    pub fn initial_state<W: StateWord>(
+        &self,
+        initial_value: u64,
+        sum_frequencies: u32,
+    ) -> Result<W, AnsError> {
+        if initial_value == 0 {
+            return Err(AnsError::ZeroInitialValue);
+        }
+        let overflow_bits = self.underflow_bits as u32 + self.quantum_bits as u32;
+        initial_value
+            .checked_add(sum_frequencies.saturating_sub(1) as u64)
+            .filter(|&x| overflow_bits >= 64 || x >> overflow_bits == 0)
+            .and_then(W::try_from_u64)
+            .ok_or_else(|| {
+                AnsError::Unbalanced(format!(
+                    "initial value {} does not fit below 1<<{}",
+                    initial_value, overflow_bits
+                ))
+            })
+    }
+
+    /// `steps` come in the order they are encoded, which is the reverse of the message, and
+    /// `append(x, step)` encodes one of them onto the state `x`.
+    pub fn encode<W, I, E>(
+        &self,
+        steps: I,
+        mut append: impl FnMut(W, &I::Item) -> Result<W, AnsError>,
+        initial_state: W,
+        sink: &mut dyn FnMut(u8) -> Result<(), E>,
+    ) -> Result<(), EncodeFailure<E>>
+    where
+        W: StateWord,
+        I: Iterator,
+        I::Item: Debug,
+    {
+        let overflow_bits = self.underflow_bits as u32 + self.quantum_bits as u32;
+        let mut quanta = QuantumSink::new(sink);
+        let mut x = initial_state;
+
+        for step in steps {
+            let mut new_x = append(x, &step).map_err(EncodeFailure::Coder)?;
+            if new_x >> overflow_bits != W::ZERO {
+                self.emit(|| format!("{:x}.{:?} overflows to {:x}", x, step, new_x));
+                x = self.push_quantum(x, &mut quanta)?;
+                new_x = append(x, &step).map_err(EncodeFailure::Coder)?;
+            }
+            self.emit(|| format!("{:x}.{:?} becomes {:x}", x, step, new_x));
+            x = new_x;
+        }
+
+        while x != W::ZERO {
+            x = self.push_quantum(x, &mut quanta)?;
+        }
+        quanta.finish().map_err(EncodeFailure::Sink)
+    }
+
+    fn push_quantum<W: StateWord, E>(
+        &self,
+        x: W,
+        quanta: &mut QuantumSink<E>,
+    ) -> Result<W, EncodeFailure<E>> {
+        self.emit(|| format!("push the low {} bits of {:x}", self.quantum_bits, x));
+        quanta
+            .push(x.low_u32(), self.quantum_bits as u32)
+            .map_err(EncodeFailure::Sink)?;
+        Ok(x >> self.quantum_bits as u32)
+    }
+
+    /// Decode until the stream is used up and the state is back at `eos_state`.
+    pub fn decode<W: StateWord, D: DecodeSteps<W>>(
+        &self,
+        stream: &[u8],
+        eos_state: W,
+        steps: &mut D,
+    ) -> Result<(), AnsError> {
+        let underflow_bits = self.underflow_bits as u32;
+        let mut source = QuantumSource::new(stream);
+        let mut x = W::ZERO;
+
+        while x >> underflow_bits == W::ZERO {
+            match self.read_quantum(&mut source, x) {
+                None => break,
+                Some(new_x) => x = new_x,
+            }
+        }
+        loop {
+            if x >> underflow_bits == W::ZERO {
+                match self.read_quantum(&mut source, x) {
+                    None => break,
+                    // the quantum may have been pushed before the first symbol, right off the eos state
+                    Some(new_x) => x = new_x,
+                }
+            }
+            if x == eos_state && steps.at_boundary() && source.is_empty() {
+                break;
+            }
+            x = self.decode_step(steps, x)?;
+        }
+
+        while x != eos_state || !steps.at_boundary() {
+            let new_x = self.decode_step(steps, x)?;
+            if new_x < eos_state {
+                return Err(AnsError::EosNotReached);
+            }
+            x = new_x;
+        }
+        Ok(())
+    }
+
+    fn decode_step<W: StateWord, D: DecodeSteps<W>>(
+        &self,
+        steps: &mut D,
+        x: W,
+    ) -> Result<W, AnsError> {
+        let (symbol, new_x) = steps.decode(x)?;
+        self.emit(|| format!("{:x} becomes {:x}.{:?}", x, new_x, symbol));
+        Ok(new_x)
+    }
+
+    fn read_quantum<W: StateWord>(&self, source: &mut QuantumSource, x: W) -> Option<W> {
+        let quantum_bits = self.quantum_bits as u32;
+        let quantum = source.read(quantum_bits)?;
+        self.emit(|| format!("pulled {:x} from the stream into {:x}", quantum, x));
+        Some((x << quantum_bits) | W::from_u32(quantum))
+    }
+
+    fn emit(&self, msg: impl FnOnce() -> String) {
+        if let Some(diagnostics) = self.trace {
+            diagnostics.emit(&msg())
+        }
+    }
+}
+
+/// packs quanta least significant bit first
+struct QuantumSink<'a, E> {
+    sink: &'a mut dyn FnMut(u8) -> Result<(), E>,
+    accum: u64,
+    accum_bits: u32,
+}
+
+impl<'a, E> QuantumSink<'a, E> {
+    fn new(sink: &'a mut dyn FnMut(u8) -> Result<(), E>) -> QuantumSink<'a, E> {
+        QuantumSink {
+            sink,
+            accum: 0,
+            accum_bits: 0,
+        }
+    }
+
+    /// the low `bits` of `quantum`, at most 32
+    fn push(&mut self, quantum: u32, bits: u32) -> Result<(), E> {
+        let mask = u64::MAX >> (64 - bits);
+        self.accum |= (quantum as u64 & mask) << self.accum_bits;
+        self.accum_bits += bits;
+        while self.accum_bits >= 8 {
+            (self.sink)(self.accum as u8)?;
+            self.accum >>= 8;
+            self.accum_bits -= 8;
+        }
+        Ok(())
+    }
+
+    /// pad the final partial byte with zeros
+    fn finish(self) -> Result<(), E> {
+        if self.accum_bits > 0 {
+            (self.sink)(self.accum as u8)?;
+        }
+        Ok(())
+    }
+}
+
+/// reads what `QuantumSink` wrote, last quantum first
+struct QuantumSource<'a> {
+    bytes: &'a [u8],
+    /// bits not yet read, from the start of `bytes`
+    remaining: usize,
+}
+
+impl<'a> QuantumSource<'a> {
+    fn new(bytes: &'a [u8]) -> QuantumSource<'a> {
+        QuantumSource {
+            bytes,
+            remaining: bytes.len() * 8,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// `None` if fewer than `bits` are left
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        let start = self.remaining.checked_sub(bits as usize)?;
+        let mut accum = 0u64;
+        for &byte in self.bytes[start / 8..self.remaining.div_ceil(8)]
+            .iter()
+            .rev()
+        {
+            accum = (accum << 8) | byte as u64;
+        }
+        self.remaining = start;
+        Some(((accum >> (start % 8)) & (u64::MAX >> (64 - bits))) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        check_stream_balance, ANSTableUniform, AnsError, GenericStreamingANSUniform,
+        NormalizationStrategy, StateWord, StreamingANSUniform, SymbolFrequencies,
+    };
+
+    fn coder<W: StateWord>(
+        table: &ANSTableUniform,
+        underflow_bits: u8,
+        quantum_bits: u8,
+    ) -> GenericStreamingANSUniform<u8, 256, ANSTableUniform, W> {
+        GenericStreamingANSUniform::with_quantum_bits(table.clone(), underflow_bits, quantum_bits)
+            .unwrap()
+    }
+
+    fn round_trips<W: StateWord>(
+        table: &ANSTableUniform,
+        underflow_bits: u8,
+        quantum_bits: u8,
+        message: &[u8],
+    ) {
+        let coder = coder::<W>(table, underflow_bits, quantum_bits);
+        for &len in &[0, 1, 2, 3, 100, message.len()] {
+            let encoded = coder.encode_slice(&message[..len], 1).unwrap();
+            assert_eq!(
+                &message[..len],
+                &coder.decode(&encoded, 1).unwrap()[..],
+                "u{} {} {}",
+                W::BITS,
+                underflow_bits,
+                quantum_bits
+            );
+        }
+    }
+
+    #[test]
+    fn every_width_round_trips() {
+        let message: Vec<u8> = b"a quantum of solace, a quantum of bits, a state of any width"
+            .iter()
+            .cycle()
+            .take(3000)
+            .cloned()
+            .collect();
+        let mut raw = SymbolFrequencies::new();
+        raw.scan_file(&mut &message[..]).unwrap();
+        let freqs = raw
+            .normalize(12, NormalizationStrategy::Greedy)
+            .unwrap()
+            .frequencies;
+        let text = ANSTableUniform::new(freqs.clone());
+
+        // every width does the same arithmetic, so whole bytes give the bytes of the u64 state
+        let expected = StreamingANSUniform::new(freqs.clone(), 12, 1)
+            .encode_slice(&message, 1)
+            .unwrap();
+        assert_eq!(
+            expected,
+            coder::<u32>(&text, 12, 8)
+                .encode_slice(&message, 1)
+                .unwrap()
+        );
+        assert_eq!(
+            expected,
+            coder::<u128>(&text, 12, 8)
+                .encode_slice(&message, 1)
+                .unwrap()
+        );
+        let expected = StreamingANSUniform::new(freqs, 24, 2)
+            .encode_slice(&message, 1)
+            .unwrap();
+        assert_eq!(
+            expected,
+            coder::<u128>(&text, 24, 16)
+                .encode_slice(&message, 1)
+                .unwrap()
+        );
+
+        round_trips::<u32>(&text, 12, 8, &message);
+        round_trips::<u64>(&text, 48, 8, &message);
+        round_trips::<u64>(&text, 24, 16, &message);
+        round_trips::<u128>(&text, 96, 8, &message);
+        round_trips::<u128>(&text, 60, 32, &message);
+
+        // quanta smaller than a byte need symbols that are no rarer than 1 in 2^quantum_bits
+        let mut small = SymbolFrequencies::new();
+        small.frequencies[..4].copy_from_slice(&[5, 4, 4, 3]);
+        let small = ANSTableUniform::new(small);
+        let small_message: Vec<u8> = (0..3000u32).map(|i| ((i * i + i / 3) % 4) as u8).collect();
+        round_trips::<u32>(&small, 24, 4, &small_message);
+        round_trips::<u64>(&small, 54, 4, &small_message);
+        round_trips::<u128>(&small, 96, 4, &small_message);
+
+        let mut binary = SymbolFrequencies::new();
+        binary.frequencies[..2].copy_from_slice(&[1, 1]);
+        let binary = ANSTableUniform::new(binary);
+        let binary_message: Vec<u8> = small_message.iter().map(|&symbol| symbol & 1).collect();
+        round_trips::<u32>(&binary, 30, 1, &binary_message);
+        round_trips::<u64>(&binary, 54, 1, &binary_message);
+        round_trips::<u128>(&binary, 12, 1, &binary_message);
+
+        let unbalanced =
+            |result: Result<(), AnsError>| matches!(result, Err(AnsError::Unbalanced(_)));
+        // a symbol of 68/4096 could jump past the state with 1-bit quanta
+        assert!(unbalanced(check_stream_balance::<u128, u8, _>(
+            &text, 24, 1
+        )));
+        assert!(check_stream_balance::<u128, u8, _>(&small, 24, 1).is_err());
+        // the state has no room for 18 + 2*8 bits
+        assert!(unbalanced(check_stream_balance::<u32, u8, _>(&text, 18, 8)));
+        assert!(unbalanced(check_stream_balance::<u128, u8, _>(
+            &text, 66, 32
+        )));
+        // a quantum may not be wider than underflow_bits, nor split bytes unevenly
+        assert!(unbalanced(check_stream_balance::<u64, u8, _>(
+            &text, 12, 16
+        )));
+        assert!(unbalanced(check_stream_balance::<u64, u8, _>(&text, 16, 3)));
+    }
+
+    fn round_trips_if_accepted<W: StateWord>(
+        table: &ANSTableUniform,
+        underflow_bits: u8,
+        quantum_bits: u8,
+        messages: &[Vec<u8>],
+    ) -> usize {
+        let coder =
+            match GenericStreamingANSUniform::<u8, 256, ANSTableUniform, W>::with_quantum_bits(
+                table.clone(),
+                underflow_bits,
+                quantum_bits,
+            ) {
+                Ok(coder) => coder,
+                Err(_) => return 0,
+            };
+        let mut accepted = 0;
+        for &iv in &[1, 1000, 1 << 20, 1 << 40] {
+            for message in messages {
+                let encoded = match coder.encode_slice(message, iv) {
+                    Ok(encoded) => encoded,
+                    Err(AnsError::Unbalanced(_)) => continue,
+                    Err(e) => panic!("{}", e),
+                };
+                assert_eq!(
+                    Ok(&message[..]),
+                    coder.decode(&encoded, iv).as_deref(),
+                    "u{} {} {} {} {:?}",
+                    W::BITS,
+                    underflow_bits,
+                    quantum_bits,
+                    iv,
+                    table.frequencies
+                );
+                accepted += 1;
+            }
+        }
+        accepted
+    }
+
+    #[test]
+    fn every_accepted_config_round_trips() {
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let mut tables = Vec::new();
+        for raw in &[
+            &[1, 1][..],
+            &[5, 4, 4, 3],
+            &[5, 4, 4, 2],
+            &[40, 7, 2, 1, 1, 9],
+        ] {
+            let mut freqs = SymbolFrequencies::new();
+            freqs.frequencies[..raw.len()].copy_from_slice(raw);
+            tables.push(freqs.clone());
+            for &precision_bits in &[8, 12, 16] {
+                tables.push(
+                    freqs
+                        .normalize(precision_bits, NormalizationStrategy::Greedy)
+                        .unwrap()
+                        .frequencies,
+                );
+            }
+        }
+
+        let mut accepted = 0;
+        for freqs in tables {
+            let present: Vec<u8> = (0..=255u8)
+                .filter(|&symbol| freqs.frequencies[symbol as usize] > 0)
+                .collect();
+            let messages: Vec<Vec<u8>> = (0..8)
+                .map(|_| {
+                    let len = random() as usize % 200;
+                    (0..len)
+                        .map(|_| present[random() as usize % present.len()])
+                        .collect()
+                })
+                .collect();
+            let table = ANSTableUniform::new(freqs);
+            for &underflow_bits in &[4, 8, 12, 16, 20, 24, 32, 48, 64, 96] {
+                for &quantum_bits in &[1, 2, 4, 8, 16, 24, 32] {
+                    accepted += round_trips_if_accepted::<u32>(
+                        &table,
+                        underflow_bits,
+                        quantum_bits,
+                        &messages,
+                    );
+                    accepted += round_trips_if_accepted::<u64>(
+                        &table,
+                        underflow_bits,
+                        quantum_bits,
+                        &messages,
+                    );
+                    accepted += round_trips_if_accepted::<u128>(
+                        &table,
+                        underflow_bits,
+                        quantum_bits,
+                        &messages,
+                    );
+                }
+            }
+        }
+        assert!(accepted > 1000, "only {} configs accepted", accepted);
+
+        // a table wider than the underflow window, and states that start past the first quantum
+        let mut wide = SymbolFrequencies::new();
+        wide.frequencies[..3].copy_from_slice(&[1 << 15, 1 << 14, 1 << 14]);
+        assert!(StreamingANSUniform::try_new(wide, 12, 1).is_err());
+        let mut small = SymbolFrequencies::new();
+        small.frequencies[..2].copy_from_slice(&[12, 4]);
+        let small = ANSTableUniform::new(small);
+        let coder = GenericStreamingANSUniform::<u8, 256, ANSTableUniform, u64>::with_quantum_bits(
+            small, 12, 4,
+        )
+        .unwrap();
+        assert!(matches!(
+            coder.encode_slice(b"\x00", 1 << 20),
+            Err(AnsError::Unbalanced(_))
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+//! Versioned on-disk format for `SymbolFrequencies`.
+//!
+//! Layout (all integers big-endian, matching the legacy format):
+//!
+//! | bytes | field                                                   |
+//! |-------|---------------------------------------------------------|
+//! | 4     | magic `\x89ANS`                                         |
+//! | 1     | version (currently 1)                                   |
+//! | 1     | normalization precision in bits, 0 for raw counts       |
+//! | 2     | reserved, 0                                             |
+//! | 4     | alphabet size                                           |
+//! | 8     | sum of all frequencies                                  |
+//! | 4     | CRC-32 (IEEE) of the frequency payload                  |
+//! | 4*n   | one u32 frequency per symbol                            |
+//!
+//! The legacy format is just the u32 frequencies (256 of them for bytes) with no header.  The magic was chosen
+//! so that a legacy file would need a frequency of more than 2^31 for symbol 0 to be mistaken for it.
+
+use crate::GenericSymbolFrequencies;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Error, ErrorKind, Read, Write};
+
+pub const TABLE_MAGIC: [u8; 4] = *b"\x89ANS";
+pub const TABLE_VERSION: u8 = 1;
+
+/// The metadata stored in front of the frequencies in a versioned table file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableHeader {
+    pub version: u8,
+    /// `Some(n)` if the frequencies were normalized to sum to `1<<n`
+    pub precision_bits: Option<u8>,
+    pub alphabet_size: u32,
+    pub total: u64,
+    pub crc: u32,
+}
+
+impl TableHeader {
+    pub fn describe<const N: usize>(
+        freqs: &GenericSymbolFrequencies<N>,
+        precision_bits: Option<u8>,
+    ) -> TableHeader {
+        TableHeader {
+            version: TABLE_VERSION,
+            precision_bits,
+            alphabet_size: freqs.frequencies.len() as u32,
+            total: freqs.frequencies.iter().map(|&f| f as u64).sum(),
+            crc: crc32_of_frequencies(&freqs.frequencies),
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    /// Read a frequency table in either the versioned format or the legacy headerless format
+    /// (1024 bytes for the byte alphabet).
+    pub fn parse_symbol_table(src: &mut dyn Read) -> Result<GenericSymbolFrequencies<N>, Error> {
+        Self::parse_symbol_table_with_header(src).map(|(_, freqs)| freqs)
+    }
+
+    /// Like `parse_symbol_table`, but also returns the header.
+    /// Legacy files get `None` because they don't have one.
+    pub fn parse_symbol_table_with_header(
+        src: &mut dyn Read,
+    ) -> Result<(Option<TableHeader>, GenericSymbolFrequencies<N>), Error> {
+        let mut magic = [0u8; 4];
+        src.read_exact(&mut magic)?;
+        if magic != TABLE_MAGIC {
+            let mut legacy = (&magic[..]).chain(src);
+            return Ok((None, Self::parse_binary_symbol_table(&mut legacy)?));
+        }
+
+        let version = src.read_u8()?;
+        if version != TABLE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported symbol table version {} (expected {})",
+                version, TABLE_VERSION
+            )));
+        }
+        let precision_bits = match src.read_u8()? {
+            0 => None,
+            bits => Some(bits),
+        };
+        let _reserved = src.read_u16::<BigEndian>()?;
+        let alphabet_size = src.read_u32::<BigEndian>()?;
+        let total = src.read_u64::<BigEndian>()?;
+        let crc = src.read_u32::<BigEndian>()?;
+        let header = TableHeader {
+            version,
+            precision_bits,
+            alphabet_size,
+            total,
+            crc,
+        };
+
+        let mut rval = GenericSymbolFrequencies::<N>::new();
+        if alphabet_size as usize != rval.frequencies.len() {
+            return Err(invalid_data(format!(
+                "symbol table has an alphabet of {} symbols, expected {}",
+                alphabet_size,
+                rval.frequencies.len()
+            )));
+        }
+        src.read_u32_into::<BigEndian>(&mut rval.frequencies)?;
+
+        let actual = TableHeader::describe(&rval, precision_bits);
+        if actual.crc != crc {
+            return Err(invalid_data(format!(
+                "symbol table CRC mismatch: header says {:08x}, payload is {:08x}",
+                crc, actual.crc
+            )));
+        }
+        if actual.total != total {
+            return Err(invalid_data(format!(
+                "symbol table total mismatch: header says {}, frequencies sum to {}",
+                total, actual.total
+            )));
+        }
+        if let Some(bits) = precision_bits {
+            if bits >= 64 || total != 1u64 << bits {
+                return Err(invalid_data(format!(
+                    "symbol table claims {}-bit normalization but sums to {}",
+                    bits, total
+                )));
+            }
+        }
+
+        Ok((Some(header), rval))
+    }
+
+    /// Write this table in the versioned format.
+    ///
+    /// Pass `Some(precision_bits)` if the frequencies have been normalized to sum to `1<<precision_bits`.
+    pub fn write_symbol_table(
+        &self,
+        sink: &mut dyn Write,
+        precision_bits: Option<u8>,
+    ) -> Result<(), Error> {
+        let header = TableHeader::describe(self, precision_bits);
+        if let Some(bits) = precision_bits {
+            if bits == 0 || bits >= 64 || header.total != 1u64 << bits {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "frequencies sum to {}, which is not a {}-bit normalization",
+                        header.total, bits
+                    ),
+                ));
+            }
+        }
+
+        sink.write_all(&TABLE_MAGIC)?;
+        sink.write_u8(header.version)?;
+        sink.write_u8(precision_bits.unwrap_or(0))?;
+        sink.write_u16::<BigEndian>(0)?;
+        sink.write_u32::<BigEndian>(header.alphabet_size)?;
+        sink.write_u64::<BigEndian>(header.total)?;
+        sink.write_u32::<BigEndian>(header.crc)?;
+        for &freq in self.frequencies.iter() {
+            sink.write_u32::<BigEndian>(freq)?;
+        }
+        Ok(())
+    }
+}
+
+/// CRC-32 (the IEEE/zlib polynomial) of the big-endian serialization of `frequencies`
+fn crc32_of_frequencies(frequencies: &[u32]) -> u32 {
+    let mut crc = !0u32;
+    for &freq in frequencies {
+        for &byte in &freq.to_be_bytes() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SymbolFrequencies;
+
+    fn sample() -> SymbolFrequencies {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[b'a' as usize] = 3;
+        freqs.frequencies[b'b' as usize] = 1;
+        freqs.frequencies[255] = 12;
+        freqs
+    }
+
+    #[test]
+    fn versioned_round_trip() {
+        let freqs = sample();
+        let mut buffer = Vec::new();
+        freqs.write_symbol_table(&mut buffer, Some(4)).unwrap();
+        assert_eq!(24 + 1024, buffer.len());
+
+        let (header, parsed) =
+            SymbolFrequencies::parse_symbol_table_with_header(&mut &buffer[..]).unwrap();
+        let header = header.unwrap();
+        assert_eq!(Some(4), header.precision_bits);
+        assert_eq!(16, header.total);
+        assert_eq!(&freqs.frequencies[..], &parsed.frequencies[..]);
+    }
+
+    #[test]
+    fn legacy_still_parses() {
+        let freqs = sample();
+        let mut buffer = Vec::new();
+        for &freq in freqs.frequencies.iter() {
+            buffer.extend_from_slice(&freq.to_be_bytes());
+        }
+        let (header, parsed) =
+            SymbolFrequencies::parse_symbol_table_with_header(&mut &buffer[..]).unwrap();
+        assert_eq!(None, header);
+        assert_eq!(&freqs.frequencies[..], &parsed.frequencies[..]);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let mut buffer = Vec::new();
+        sample().write_symbol_table(&mut buffer, None).unwrap();
+        let last = buffer.len() - 1;
+        buffer[last] ^= 1;
+        assert!(SymbolFrequencies::parse_symbol_table(&mut &buffer[..]).is_err());
+
+        assert!(sample()
+            .write_symbol_table(&mut Vec::new(), Some(5))
+            .is_err());
+    }
+}
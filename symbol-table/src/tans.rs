@@ -0,0 +1,325 @@
+//! Tabled ANS (tANS, as in FSE): the coder as a state machine over `L = 2^R` states with bit-granular output.
+//!
+//! The frequencies must be normalized to sum to `L`, and the state stays in `[L, 2L)`.
+//! Encoding a symbol of frequency `f` shifts out just enough low bits to bring the state into `[f, 2f)`
+//! and looks up the successor; decoding looks up the symbol, how many bits to read, and what to add them to.
+//! Neither direction divides.
+//!
+//! The stream is the Elias gamma code of `len+1`, the final encoder state in `R` bits,
+//! and then the shifted-out bits in the order the decoder consumes them.
+
+use crate::bit_io::{bit_length, gamma_length, BitReader, BitWriter};
+use crate::{AnsError, GenericANSTableUniform, GenericSymbolFrequencies, SpreadStrategy, Symbol};
+use std::io::{Error, Write};
+
+/// `u32` states need `R+1` bits
+pub const MAX_TANS_TABLE_BITS: u8 = 30;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct EncodeTransform {
+    frequency: u32,
+    /// where this symbol's successors start in `next_state`
+    start: u32,
+    /// states at or above `threshold` shift out `max_bits`, the rest one fewer
+    threshold: u32,
+    max_bits: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct DecodeEntry<S> {
+    symbol: S,
+    bits: u8,
+    /// the predecessor state, before its low `bits` were shifted out
+    base: u32,
+}
+
+pub struct GenericTabledANS<S: Symbol, const N: usize> {
+    pub table_bits: u8,
+    transforms: Vec<EncodeTransform>,
+    /// successor states grouped by symbol, indexed by `start + (x >> bits) - frequency`
+    next_state: Vec<u32>,
+    /// indexed by `x - L`
+    decode: Vec<DecodeEntry<S>>,
+    /// the most steps in a row that read no bits, or `None` if they can go on forever
+    max_silent_steps: Option<u64>,
+}
+
+pub type TabledANS = GenericTabledANS<u8, 256>;
+
+impl<S: Symbol, const N: usize> GenericTabledANS<S, N> {
+    /// `freqs` must sum to a power of two no bigger than `2^MAX_TANS_TABLE_BITS`.
+    pub fn new(
+        freqs: GenericSymbolFrequencies<N>,
+        strategy: &dyn SpreadStrategy,
+    ) -> Result<GenericTabledANS<S, N>, AnsError> {
+        Self::from_table(&GenericANSTableUniform::with_strategy(freqs, strategy)?)
+    }
+
+    /// Reuse the spread of a table built for the streaming coder.
+    pub fn from_table(
+        table: &GenericANSTableUniform<S, N>,
+    ) -> Result<GenericTabledANS<S, N>, AnsError> {
+        let sum_frequencies = table.sum_frequencies;
+        if !sum_frequencies.is_power_of_two()
+            || sum_frequencies.trailing_zeros() > MAX_TANS_TABLE_BITS as u32
+        {
+            return Err(AnsError::InvalidTable(format!(
+                "tANS needs frequencies that sum to a power of two up to 2^{}, not {}",
+                MAX_TANS_TABLE_BITS, sum_frequencies
+            )));
+        }
+        let table_bits = sum_frequencies.trailing_zeros() as u8;
+        let l = sum_frequencies;
+
+        let mut transforms = vec![EncodeTransform::default(); N];
+        let mut next_state = Vec::with_capacity(l as usize);
+        for (transform, (&frequency, slots)) in transforms
+            .iter_mut()
+            .zip(table.frequencies.iter().zip(table.encode.iter()))
+        {
+            if frequency == 0 {
+                continue;
+            }
+            let max_bits = table_bits + 1 - bit_length(frequency as u64);
+            *transform = EncodeTransform {
+                frequency,
+                start: next_state.len() as u32,
+                threshold: frequency << max_bits,
+                max_bits,
+            };
+            next_state.extend(slots.iter().map(|&slot| l + slot));
+        }
+
+        let decode = table
+            .decode
+            .iter()
+            .map(|&(symbol, k)| {
+                let y = table.frequencies[symbol.to_index()] + k;
+                let bits = table_bits + 1 - bit_length(y as u64);
+                DecodeEntry {
+                    symbol,
+                    bits,
+                    base: y << bits,
+                }
+            })
+            .collect::<Vec<_>>();
+        let max_silent_steps = max_silent_steps(&decode, l);
+
+        Ok(GenericTabledANS {
+            table_bits,
+            transforms,
+            next_state,
+            decode,
+            max_silent_steps,
+        })
+    }
+
+    /// Encode `message`.  The decoder gets the symbols back in the same order.
+    pub fn encode(&self, message: &[S]) -> Result<Vec<u8>, AnsError> {
+        let l = 1u32 << self.table_bits;
+        let mut x = l;
+        // the decoder reads the bits of the last symbol encoded first
+        let mut chunks: Vec<(u32, u8)> = Vec::with_capacity(message.len());
+        for &symbol in message.iter().rev() {
+            let transform = self
+                .transforms
+                .get(symbol.to_index())
+                .filter(|t| t.frequency > 0)
+                .ok_or(AnsError::SymbolNotInTable {
+                    symbol: symbol.to_index(),
+                })?;
+            let bits = transform.max_bits - (x < transform.threshold) as u8;
+            chunks.push((x & ((1 << bits) - 1), bits));
+            let y = x >> bits;
+            x = self.next_state[(transform.start + y - transform.frequency) as usize];
+        }
+
+        let mut rval = Vec::new();
+        self.write_stream(&mut rval, message.len(), x - l, &chunks)
+            .expect("writing to a Vec can not fail");
+        Ok(rval)
+    }
+
+    fn write_stream(
+        &self,
+        sink: &mut dyn Write,
+        len: usize,
+        final_state: u32,
+        chunks: &[(u32, u8)],
+    ) -> Result<(), Error> {
+        let mut writer = BitWriter::new(sink);
+        writer.write_gamma(len as u64 + 1)?;
+        writer.write_bits(final_state as u64, self.table_bits)?;
+        for &(val, bits) in chunks.iter().rev() {
+            writer.write_bits(val as u64, bits)?;
+        }
+        writer.flush()
+    }
+
+    /// Fails with `EosNotReached` if the stream is truncated, claims more symbols than its bits could
+    /// hold, or does not end in the initial state.
+    ///
+    /// A table that can decode forever without reading a bit (one with a single symbol) gives no such
+    /// bound; decode with it through `decode_limited`.
+    pub fn decode(&self, encoded: &[u8]) -> Result<Vec<S>, AnsError> {
+        self.decode_within(encoded, None)
+    }
+
+    /// `decode`, but fails with `OutputTooSmall` instead of decoding more than `max_len` symbols.
+    pub fn decode_limited(&self, encoded: &[u8], max_len: usize) -> Result<Vec<S>, AnsError> {
+        self.decode_within(encoded, Some(max_len))
+    }
+
+    fn decode_within(&self, encoded: &[u8], max_len: Option<usize>) -> Result<Vec<S>, AnsError> {
+        let l = 1u32 << self.table_bits;
+        let mut src = encoded;
+        let mut reader = BitReader::new(&mut src);
+        let truncated = |_| AnsError::EosNotReached;
+
+        let len = reader.read_gamma().map_err(truncated)? - 1;
+        let header_bits = gamma_length(len + 1) + self.table_bits as u64;
+        let stream_bits = (encoded.len() as u64 * 8).saturating_sub(header_bits);
+        match (max_len, self.max_silent_steps) {
+            (Some(max_len), _) if len > max_len as u64 => {
+                return Err(AnsError::OutputTooSmall { capacity: max_len })
+            }
+            (Some(_), _) => {}
+            // every run of silent steps ends in a step that reads at least one bit, or the end
+            (None, Some(silent)) => {
+                if len > (stream_bits + 1).saturating_mul(silent + 1) {
+                    return Err(AnsError::EosNotReached);
+                }
+            }
+            (None, None) => {
+                return Err(AnsError::InvalidTable(
+                    "the table can decode without reading bits forever; use decode_limited".into(),
+                ))
+            }
+        }
+        let mut x = l + reader.read_bits(self.table_bits).map_err(truncated)? as u32;
+        let mut rval = Vec::with_capacity(len.min(stream_bits) as usize);
+        for _ in 0..len {
+            let entry = &self.decode[(x - l) as usize];
+            rval.push(entry.symbol);
+            x = entry.base + reader.read_bits(entry.bits).map_err(truncated)? as u32;
+        }
+        if x != l {
+            return Err(AnsError::EosNotReached);
+        }
+        Ok(rval)
+    }
+}
+
+/// The longest chain of decode steps that read no bits, following each state to the next.
+fn max_silent_steps<S>(decode: &[DecodeEntry<S>], l: u32) -> Option<u64> {
+    const UNKNOWN: u64 = u64::MAX;
+    const VISITING: u64 = u64::MAX - 1;
+    let mut run = vec![UNKNOWN; decode.len()];
+    let mut longest = 0;
+    for start in 0..decode.len() {
+        let mut path = Vec::new();
+        let mut index = start;
+        let mut tail = loop {
+            match run[index] {
+                VISITING => return None,
+                UNKNOWN if decode[index].bits == 0 => {
+                    run[index] = VISITING;
+                    path.push(index);
+                    index = (decode[index].base - l) as usize;
+                }
+                UNKNOWN => break 0,
+                known => break known,
+            }
+        };
+        for &index in path.iter().rev() {
+            tail += 1;
+            run[index] = tail;
+        }
+        if run[start] == UNKNOWN {
+            run[start] = 0;
+        }
+        longest = longest.max(run[start]);
+    }
+    Some(longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AnsError, NormalizationStrategy, SpreadMethod, SymbolFrequencies, TabledANS};
+
+    #[test]
+    fn round_trip() {
+        let message: Vec<u8> = b"it was a dark and stormy night; the rain fell in torrents"
+            .iter()
+            .cycle()
+            .take(5000)
+            .cloned()
+            .collect();
+        let mut raw = SymbolFrequencies::new();
+        raw.scan_file(&mut &message[..]).unwrap();
+
+        for &bits in &[6, 11, 16] {
+            let freqs = raw
+                .normalize(bits, NormalizationStrategy::Greedy)
+                .unwrap()
+                .frequencies;
+            for &method in &[SpreadMethod::Precise { phase: 0 }, SpreadMethod::Step] {
+                let coder = TabledANS::new(freqs.clone(), &method).unwrap();
+                let encoded = coder.encode(&message).unwrap();
+                assert_eq!(
+                    message,
+                    coder.decode(&encoded).unwrap(),
+                    "{} {:?}",
+                    bits,
+                    method
+                );
+                assert!(encoded.len() < message.len() * 5 / 8);
+            }
+        }
+
+        let mut single = SymbolFrequencies::new();
+        single.frequencies[b'x' as usize] = 4;
+        let coder = TabledANS::new(single, &SpreadMethod::Step).unwrap();
+        let encoded = coder.encode(b"xxx").unwrap();
+        assert_eq!(b"xxx".to_vec(), coder.decode_limited(&encoded, 3).unwrap());
+        assert_eq!(
+            Some(AnsError::OutputTooSmall { capacity: 2 }),
+            coder.decode_limited(&encoded, 2).err()
+        );
+        assert!(matches!(
+            coder.decode(&encoded),
+            Err(AnsError::InvalidTable(_))
+        ));
+        assert_eq!(
+            Some(AnsError::SymbolNotInTable {
+                symbol: b'y' as usize
+            }),
+            coder.encode(b"xy").err()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_tables_and_streams() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[0] = 3;
+        freqs.frequencies[1] = 2;
+        assert!(TabledANS::new(freqs.clone(), &SpreadMethod::Step).is_err());
+
+        freqs.frequencies[1] = 5;
+        let coder = TabledANS::new(freqs, &SpreadMethod::Step).unwrap();
+        let encoded = coder.encode(&[0, 1, 1, 0, 1, 0, 0, 0, 1]).unwrap();
+        assert_eq!(
+            Some(AnsError::EosNotReached),
+            coder.decode(&encoded[..encoded.len() - 1]).err()
+        );
+
+        // a length of about 2^40 in front of a few bytes is refused before decoding anything
+        let mut huge = vec![0, 0, 0, 0, 0, 0x80, 0, 0, 0, 0, 0];
+        huge.extend_from_slice(&encoded);
+        assert_eq!(Some(AnsError::EosNotReached), coder.decode(&huge).err());
+        assert_eq!(
+            Some(AnsError::OutputTooSmall { capacity: 100 }),
+            coder.decode_limited(&huge, 100).err()
+        );
+    }
+}
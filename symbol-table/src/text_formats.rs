@@ -0,0 +1,355 @@
+//! Human-editable representations of `SymbolFrequencies`, and conversion between every format the crate knows.
+//!
+//! * text: `sym freq` per line, which is what `measure -O` writes
+//! * listing: `[sym]\tx freq` per line for the symbols that occur, which is what `measure` prints
+//! * CSV: `symbol,frequency` with an optional header line
+//! * JSON: `{"alphabet_size": 256, "frequencies": [...]}`.  The reader also accepts
+//!   `"frequencies": {"97": 3, ...}` for sparse tables.
+//!
+//! The text and listing readers accept each other's lines and skip blank lines and `#` comments.
+//! Symbols that aren't mentioned have a frequency of 0.
+
+use crate::GenericSymbolFrequencies;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    /// the versioned binary format of `write_symbol_table` (legacy files are accepted when reading)
+    Binary,
+    /// the bit-packed format of `write_compact`
+    Compact,
+    Text,
+    Listing,
+    Csv,
+    Json,
+}
+
+impl TableFormat {
+    /// `.bin`, `.ans`, `.txt`, `.lst`, `.csv` or `.json`
+    pub fn from_file_name(fname: &str) -> Option<TableFormat> {
+        let extension = Path::new(fname).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "bin" => Some(TableFormat::Binary),
+            "ans" => Some(TableFormat::Compact),
+            "txt" => Some(TableFormat::Text),
+            "lst" => Some(TableFormat::Listing),
+            "csv" => Some(TableFormat::Csv),
+            "json" => Some(TableFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+fn line_error(line_number: usize, msg: String) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line_number, msg),
+    )
+}
+
+impl<const N: usize> GenericSymbolFrequencies<N> {
+    /// Read a table in any `format`.  The precision is only known for the binary formats.
+    pub fn read_table_format(
+        src: &mut dyn Read,
+        format: TableFormat,
+    ) -> Result<(GenericSymbolFrequencies<N>, Option<u8>), Error> {
+        match format {
+            TableFormat::Binary => {
+                let (header, freqs) = Self::parse_symbol_table_with_header(src)?;
+                Ok((freqs, header.and_then(|h| h.precision_bits)))
+            }
+            TableFormat::Compact => Self::parse_compact(src),
+            TableFormat::Text | TableFormat::Listing => Ok((Self::parse_text(src)?, None)),
+            TableFormat::Csv => Ok((Self::parse_csv(src)?, None)),
+            TableFormat::Json => Ok((Self::parse_json(src)?, None)),
+        }
+    }
+
+    /// Write this table in any `format`.  `precision_bits` is only recorded by the binary formats.
+    pub fn write_table_format(
+        &self,
+        sink: &mut dyn Write,
+        format: TableFormat,
+        precision_bits: Option<u8>,
+    ) -> Result<(), Error> {
+        match format {
+            TableFormat::Binary => self.write_symbol_table(sink, precision_bits),
+            TableFormat::Compact => self.write_compact(sink, precision_bits).map(|_| ()),
+            TableFormat::Text => self.write_text(sink),
+            TableFormat::Listing => self.write_listing(sink),
+            TableFormat::Csv => self.write_csv(sink),
+            TableFormat::Json => self.write_json(sink),
+        }
+    }
+
+    pub fn write_text(&self, sink: &mut dyn Write) -> Result<(), Error> {
+        for (sym, freq) in self.frequencies.iter().enumerate() {
+            writeln!(sink, "{} {}", sym, freq)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_listing(&self, sink: &mut dyn Write) -> Result<(), Error> {
+        for (symbol, &freq) in self.frequencies.iter().enumerate() {
+            if freq > 0 {
+                writeln!(sink, "[{}]\tx {}", symbol, freq)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// parse the output of `write_text` or `write_listing`
+    pub fn parse_text(src: &mut dyn Read) -> Result<GenericSymbolFrequencies<N>, Error> {
+        let mut rval = TableBuilder::new();
+        for (i, line) in BufReader::new(src).lines().enumerate() {
+            let line_number = i + 1;
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let (symbol, freq) = match tokens[..] {
+                [symbol, freq] => (symbol, freq),
+                [symbol, "x", freq] if symbol.starts_with('[') && symbol.ends_with(']') => {
+                    (&symbol[1..symbol.len() - 1], freq)
+                }
+                _ => {
+                    return Err(line_error(
+                        line_number,
+                        format!("expected `sym freq` or `[sym]\\tx freq`, not {:?}", line),
+                    ))
+                }
+            };
+            rval.set(line_number, symbol, freq)?;
+        }
+        Ok(rval.table)
+    }
+
+    pub fn write_csv(&self, sink: &mut dyn Write) -> Result<(), Error> {
+        writeln!(sink, "symbol,frequency")?;
+        for (sym, freq) in self.frequencies.iter().enumerate() {
+            writeln!(sink, "{},{}", sym, freq)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_csv(src: &mut dyn Read) -> Result<GenericSymbolFrequencies<N>, Error> {
+        let mut rval = TableBuilder::new();
+        let mut seen_data = false;
+        for (i, line) in BufReader::new(src).lines().enumerate() {
+            let line_number = i + 1;
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+            if fields.len() != 2 {
+                return Err(line_error(
+                    line_number,
+                    format!("expected 2 fields, found {}", fields.len()),
+                ));
+            }
+            let is_header = fields[0].parse::<u64>().is_err();
+            if is_header && !seen_data {
+                seen_data = true;
+                continue;
+            }
+            seen_data = true;
+            rval.set(line_number, fields[0], fields[1])?;
+        }
+        Ok(rval.table)
+    }
+
+    pub fn write_json(&self, sink: &mut dyn Write) -> Result<(), Error> {
+        writeln!(sink, "{{")?;
+        writeln!(sink, "  \"alphabet_size\": {},", N)?;
+        write!(sink, "  \"frequencies\": [")?;
+        for (sym, freq) in self.frequencies.iter().enumerate() {
+            if sym % 16 == 0 {
+                write!(sink, "\n    ")?;
+            }
+            write!(sink, "{}", freq)?;
+            if sym + 1 < N {
+                write!(sink, ", ")?;
+            }
+        }
+        writeln!(sink, "\n  ]")?;
+        writeln!(sink, "}}")?;
+        Ok(())
+    }
+
+    pub fn parse_json(src: &mut dyn Read) -> Result<GenericSymbolFrequencies<N>, Error> {
+        let value: serde_json::Value = serde_json::from_reader(src)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+
+        if let Some(alphabet_size) = value.get("alphabet_size") {
+            if alphabet_size.as_u64() != Some(N as u64) {
+                return Err(invalid(format!(
+                    "alphabet_size is {}, expected {}",
+                    alphabet_size, N
+                )));
+            }
+        }
+
+        let mut rval = TableBuilder::new();
+        match value.get("frequencies") {
+            Some(serde_json::Value::Array(list)) => {
+                if list.len() > N {
+                    return Err(invalid(format!(
+                        "{} frequencies is more than the {} symbols of the alphabet",
+                        list.len(),
+                        N
+                    )));
+                }
+                for (symbol, freq) in list.iter().enumerate() {
+                    rval.set_json(&format!("frequencies[{}]", symbol), symbol, freq)?;
+                }
+            }
+            Some(serde_json::Value::Object(map)) => {
+                for (symbol, freq) in map {
+                    let context = format!("frequencies[{:?}]", symbol);
+                    let symbol = symbol.parse().map_err(|_| {
+                        invalid(format!("{}: {:?} is not a symbol", context, symbol))
+                    })?;
+                    rval.set_json(&context, symbol, freq)?;
+                }
+            }
+            _ => {
+                return Err(invalid(
+                    "expected a \"frequencies\" array or object".to_string(),
+                ))
+            }
+        }
+        Ok(rval.table)
+    }
+}
+
+/// shared validation for the readers
+struct TableBuilder<const N: usize> {
+    table: GenericSymbolFrequencies<N>,
+    seen: Vec<bool>,
+}
+
+impl<const N: usize> TableBuilder<N> {
+    fn new() -> TableBuilder<N> {
+        TableBuilder {
+            table: GenericSymbolFrequencies::new(),
+            seen: vec![false; N],
+        }
+    }
+
+    fn set(&mut self, line_number: usize, symbol: &str, freq: &str) -> Result<(), Error> {
+        let symbol: usize = symbol
+            .parse()
+            .map_err(|_| line_error(line_number, format!("{:?} is not a symbol", symbol)))?;
+        let freq: u32 = freq.parse().map_err(|_| {
+            line_error(
+                line_number,
+                format!("{:?} is not a frequency between 0 and {}", freq, u32::MAX),
+            )
+        })?;
+        self.store(symbol, freq)
+            .map_err(|msg| line_error(line_number, msg))
+    }
+
+    fn set_json(
+        &mut self,
+        context: &str,
+        symbol: usize,
+        freq: &serde_json::Value,
+    ) -> Result<(), Error> {
+        let invalid =
+            |msg: String| Error::new(ErrorKind::InvalidData, format!("{}: {}", context, msg));
+        let freq = freq
+            .as_u64()
+            .filter(|&f| f <= u32::MAX as u64)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{} is not a frequency between 0 and {}",
+                    freq,
+                    u32::MAX
+                ))
+            })?;
+        self.store(symbol, freq as u32).map_err(invalid)
+    }
+
+    fn store(&mut self, symbol: usize, freq: u32) -> Result<(), String> {
+        if symbol >= N {
+            return Err(format!(
+                "symbol {} is outside the alphabet of {}",
+                symbol, N
+            ));
+        }
+        if self.seen[symbol] {
+            return Err(format!("symbol {} appears more than once", symbol));
+        }
+        self.seen[symbol] = true;
+        self.table.frequencies[symbol] = freq;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolFrequencies, TableFormat};
+
+    #[test]
+    fn every_format_round_trips() {
+        let mut freqs = SymbolFrequencies::new();
+        freqs.frequencies[10] = 7;
+        freqs.frequencies[b'e' as usize] = 1234;
+        freqs.frequencies[255] = 1;
+
+        for &format in &[
+            TableFormat::Binary,
+            TableFormat::Compact,
+            TableFormat::Text,
+            TableFormat::Listing,
+            TableFormat::Csv,
+            TableFormat::Json,
+        ] {
+            let mut buffer = Vec::new();
+            freqs.write_table_format(&mut buffer, format, None).unwrap();
+            let (parsed, _) =
+                SymbolFrequencies::read_table_format(&mut &buffer[..], format).unwrap();
+            assert_eq!(
+                &freqs.frequencies[..],
+                &parsed.frequencies[..],
+                "{:?}",
+                format
+            );
+        }
+
+        assert_eq!(
+            Some(TableFormat::Json),
+            TableFormat::from_file_name("tables.v2/english.JSON")
+        );
+        assert_eq!(None, TableFormat::from_file_name("json"));
+        assert_eq!(None, TableFormat::from_file_name("tables.csv/english"));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let text = "# hand edited\n97 10\n\n[98]\tx 3\n98 oops\n";
+        let err = SymbolFrequencies::parse_text(&mut text.as_bytes())
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("line 5:"), "{}", err);
+
+        let csv = "symbol,frequency\n97,1\n97,2\n";
+        let err = SymbolFrequencies::parse_csv(&mut csv.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!("line 3: symbol 97 appears more than once", err.to_string());
+
+        let json = "{\n  \"frequencies\": [1, 2,\n 3,, 4]\n}";
+        let err = SymbolFrequencies::parse_json(&mut json.as_bytes())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+    }
+}